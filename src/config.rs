@@ -0,0 +1,25 @@
+use std::{fs::File, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::fs::time::AtimePolicy;
+
+/// Settings read from `config.json`. Missing keys keep their defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Create a user on the fly when logging in with an unknown name. Off
+    /// unless enabled, and never applies to root.
+    pub create_users: bool,
+    /// Mount option deciding when reads update access times: `strict`,
+    /// `relatime` or `noatime`
+    pub atime: AtimePolicy,
+}
+
+impl Config {
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let file = File::open(path)?;
+        let config: Config = serde_json::from_reader(file)?;
+        Ok(config)
+    }
+}
@@ -1,95 +1,417 @@
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{BTreeMap, HashMap, VecDeque},
     fs::{File, OpenOptions},
     io::{Read, Seek, SeekFrom, Write},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
 use serde::{Deserialize, Serialize};
 
 use crate::user::User;
 
-use super::stdio::Stdio;
+use super::{
+    mode::ModeChange,
+    time::{AtimePolicy, Timestamp},
+};
+
+/// Size of a data page in `container.bin`.
+pub const PAGE_SIZE: u64 = 1024;
+
+/// Largest size a file may grow to, 64 MiB. Commands like `cat` and `wc`
+/// read a whole file into memory, so a hole may not be bigger than that.
+const MAX_FILE_SIZE: u64 = 64 << 20;
+
+// Flags for `FileSystem::open`, with the same values as on Linux
+pub const O_RDONLY: u32 = 0;
+pub const O_WRONLY: u32 = 0o1;
+#[allow(dead_code)]
+pub const O_RDWR: u32 = 0o2;
+pub const O_CREAT: u32 = 0o100;
+pub const O_TRUNC: u32 = 0o1000;
+pub const O_APPEND: u32 = 0o2000;
+const O_ACCMODE: u32 = 0o3;
+
+/// The first descriptor handed out by `open`; 0 to 2 are the standard streams.
+const FIRST_FD: u32 = 3;
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
 pub enum FileType {
     File,
     Directory,
+    Symlink,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
-pub enum FilePermission {
-    Readable,
-    Writable,
-    Executable,
+// Special bits of a file mode
+pub const S_ISUID: u16 = 0o4000;
+pub const S_ISGID: u16 = 0o2000;
+pub const S_ISVTX: u16 = 0o1000;
+
+// Access requested from `check_access`, as in access(2)
+const R_OK: u16 = 0o4;
+const W_OK: u16 = 0o2;
+const X_OK: u16 = 0o1;
+
+/// Bits removed from the mode of newly created files and directories.
+const UMASK: u16 = 0o022;
+
+/// Inode number of the root directory.
+const ROOT_INODE: u64 = 1;
+
+/// How many symbolic links a single lookup may follow before giving up.
+const MAX_SYMLINK_DEPTH: u32 = 40;
+
+/// Turns any spelling of an absolute path into its canonical form: repeated
+/// and trailing slashes and `.` are dropped, and `..` removes the component
+/// before it, staying at `/` when there is none.
+pub fn normalize_path(path: &str) -> String {
+    let mut components: Vec<&str> = Vec::new();
+    for component in path.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop();
+            }
+            component => components.push(component),
+        }
+    }
+    format!("/{}", components.join("/"))
 }
 
+/// An inode: everything about a file except its name, which lives in the
+/// `entries` of the directories linking to it.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct FileEntry {
-    name: String,
     file_type: FileType,
-    permission: Option<Vec<FilePermission>>,
+    /// rwx for owner, group and others plus setuid, setgid and sticky
+    mode: u16,
     owned_user: Option<String>,
+    #[serde(default)]
+    owned_group: Option<String>,
     size: u64,
-    pages: Vec<u64>,
-    parent: Option<String>,
+    /// Offsets of the data pages in the container. `None`, like any page
+    /// past the end of the list, is a hole that reads as zeros.
+    pages: Vec<Option<u64>>,
+    /// Number of directory entries referring to this file
+    #[serde(default = "default_links")]
+    links: u32,
+    /// The directory containing this one, `None` for the root and for files
+    parent: Option<u64>,
+    /// Names in this directory and the inodes they refer to
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    entries: BTreeMap<String, u64>,
+    /// Path a symbolic link points to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    target: Option<String>,
+    /// Last read of the data
+    #[serde(default)]
+    atime: Timestamp,
+    /// Last change of the data
+    #[serde(default)]
+    mtime: Timestamp,
+    /// Last change of the data or the metadata
+    #[serde(default)]
+    ctime: Timestamp,
+    /// Creation
+    #[serde(default)]
+    btime: Timestamp,
+}
+
+fn default_links() -> u32 {
+    1
+}
+
+/// Metadata of a file, as returned by `FileSystem::stat`.
+#[derive(Debug, Clone)]
+pub struct Stat {
+    pub inode: u64,
+    pub file_type: FileType,
+    pub mode: u16,
+    /// Names referring to the file. A directory counts its own entry, its
+    /// `.` and the `..` of each subdirectory.
+    pub links: u32,
+    pub owner: String,
+    pub group: String,
+    pub size: u64,
+    /// Pages of `PAGE_SIZE` bytes the file takes up in the container
+    pub pages: u64,
+    pub atime: Timestamp,
+    pub mtime: Timestamp,
+    pub ctime: Timestamp,
+    pub btime: Timestamp,
+    /// Target of a symbolic link
+    pub target: Option<String>,
+}
+
+impl Stat {
+    /// Allocated space in 512-byte blocks, like `st_blocks`.
+    pub fn blocks(&self) -> u64 {
+        self.pages * PAGE_SIZE / 512
+    }
+}
+
+/// A name in a directory, as returned by `FileSystem::read_dir`.
+#[derive(Debug, Clone)]
+pub struct DirEntry {
+    pub name: String,
+    pub stat: Stat,
+}
+
+/// An entry in the open-file table.
+#[derive(Debug, Clone)]
+struct OpenFile {
+    inode: u64,
+    flags: u32,
+    offset: u64,
+    container: PathBuf,
+}
+
+impl OpenFile {
+    fn is_readable(&self) -> bool {
+        self.flags & O_ACCMODE != O_WRONLY
+    }
+
+    fn is_writable(&self) -> bool {
+        self.flags & O_ACCMODE != O_RDONLY
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct FileSystem {
-    files: HashMap<String, FileEntry>,
+    inodes: HashMap<u64, FileEntry>,
+    next_inode: u64,
     next_offset: u64,
-    pub current_path: String,
+    /// Inode of the current working directory
+    cwd: u64,
+    /// Set on every load, and not saved so no password hash ends up here
+    #[serde(skip)]
     current_user: User,
+    /// Groups of the current user, primary group first
+    #[serde(skip)]
+    groups: Vec<String>,
     free_list: VecDeque<u64>,
-    stdio: Stdio,
+    #[serde(skip)]
+    atime_policy: AtimePolicy,
+    #[serde(skip)]
+    open_files: HashMap<u32, OpenFile>,
 }
 
 impl FileSystem {
     pub fn new(user: User) -> Self {
         let mut fs = FileSystem {
-            files: HashMap::new(),
+            inodes: HashMap::new(),
+            next_inode: ROOT_INODE + 1,
             next_offset: 0,
-            current_path: "/".to_string(),
-            current_user: user.clone(),
+            cwd: ROOT_INODE,
+            // The initial tree is set up by root
+            current_user: User::default(),
+            groups: vec!["root".to_string()],
             free_list: VecDeque::new(),
-            stdio: Stdio::new(),
+            atime_policy: AtimePolicy::default(),
+            open_files: HashMap::new(),
         };
 
         // Create the root directory
-        fs.files.insert(
-            "/".to_string(),
+        let now = Timestamp::now();
+        fs.inodes.insert(
+            ROOT_INODE,
             FileEntry {
-                name: "/".to_string(),
                 file_type: FileType::Directory,
-                permission: Some([FilePermission::Readable, FilePermission::Writable].to_vec()),
-                owned_user: Some(user.get_user_name()),
+                mode: 0o755,
+                owned_user: Some("root".to_string()),
+                owned_group: Some("root".to_string()),
                 size: 0,
                 pages: Vec::new(),
+                links: 1,
                 parent: None,
+                entries: BTreeMap::new(),
+                target: None,
+                atime: now,
+                mtime: now,
+                ctime: now,
+                btime: now,
             },
         );
+
+        // A world-writable place for users other than root
+        fs.create_file("/tmp", FileType::Directory, "root", "root", 0o777 | S_ISVTX)
+            .expect("the root directory is empty");
+
+        fs.groups = vec![user.get_group_name()];
+        fs.current_user = user;
         fs
     }
 
+    /// Absolute path of a directory, found by walking up its parents.
+    fn directory_path(&self, inode: u64) -> String {
+        let mut components = Vec::new();
+        let mut current = inode;
+        while let Some(parent) = self.inodes.get(&current).and_then(|entry| entry.parent) {
+            if let Some((name, _)) = self.inodes[&parent]
+                .entries
+                .iter()
+                .find(|(_, &child)| child == current)
+            {
+                components.push(name.as_str());
+            }
+            current = parent;
+        }
+        components.reverse();
+        format!("/{}", components.join("/"))
+    }
+
+    pub fn current_path(&self) -> String {
+        self.directory_path(self.cwd)
+    }
+
+    /// Canonical absolute form of `name`, see `normalize_path`.
     pub fn get_full_path(&self, name: &str) -> String {
         if name.starts_with("/") {
-            name.to_string()
+            normalize_path(name)
         } else {
-            if self.current_path == "/" {
-                format!("/{}", name)
-            } else {
-                format!("{}/{}", self.current_path, name)
+            normalize_path(&format!("{}/{}", self.current_path(), name))
+        }
+    }
+
+    fn entry(&self, inode: u64) -> std::io::Result<&FileEntry> {
+        self.inodes.get(&inode).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, "File or directory not found")
+        })
+    }
+
+    /// Looks up `name` in the directory `dir`, which needs search (`x`)
+    /// permission.
+    fn child(&self, dir: u64, name: &str) -> std::io::Result<u64> {
+        let entry = self.entry(dir)?;
+        if entry.file_type != FileType::Directory {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotADirectory,
+                "Not a directory",
+            ));
+        }
+        self.check_access(entry, X_OK)?;
+        entry.entries.get(name).copied().ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, "File or directory not found")
+        })
+    }
+
+    /// Resolves a path to an inode, following symbolic links.
+    fn lookup(&self, name: &str) -> std::io::Result<u64> {
+        let inode = self.walk(ROOT_INODE, &self.get_full_path(name), true, &mut 0)?;
+        self.check_trailing_slash(name, inode)
+    }
+
+    /// Resolves a path without following a symbolic link in the last
+    /// component, for operations on the link itself.
+    fn lookup_nofollow(&self, name: &str) -> std::io::Result<u64> {
+        let inode = self.walk(ROOT_INODE, &self.get_full_path(name), false, &mut 0)?;
+        self.check_trailing_slash(name, inode)
+    }
+
+    /// `name/` only ever refers to a directory.
+    fn check_trailing_slash(&self, name: &str, inode: u64) -> std::io::Result<u64> {
+        if name.ends_with('/') && self.entry(inode)?.file_type == FileType::File {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotADirectory,
+                "Not a directory",
+            ));
+        }
+        Ok(inode)
+    }
+
+    /// Walks `path` component by component starting at `dir`. Links in the
+    /// middle of the path are always followed, relative to the directory
+    /// that contains them; `depth` counts links across nested resolutions.
+    fn walk(
+        &self,
+        dir: u64,
+        path: &str,
+        follow_last: bool,
+        depth: &mut u32,
+    ) -> std::io::Result<u64> {
+        let mut current = if path.starts_with('/') {
+            ROOT_INODE
+        } else {
+            dir
+        };
+        let components: Vec<&str> = path
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .collect();
+
+        for (i, component) in components.iter().enumerate() {
+            // User paths are already normalized, but link targets may still
+            // contain `.` and `..`, which are resolved against the real tree
+            let parent = current;
+            current = match *component {
+                "." => parent,
+                ".." => self.entry(parent)?.parent.unwrap_or(ROOT_INODE),
+                _ => self.child(parent, component)?,
+            };
+
+            let entry = self.entry(current)?;
+            let is_last = i + 1 == components.len();
+            if entry.file_type == FileType::Symlink && (follow_last || !is_last) {
+                *depth += 1;
+                if *depth > MAX_SYMLINK_DEPTH {
+                    return Err(std::io::Error::other("Too many levels of symbolic links"));
+                }
+                let target = entry.target.as_deref().unwrap_or_default();
+                current = self
+                    .walk(parent, target, true, depth)
+                    .map_err(|e| match e.kind() {
+                        std::io::ErrorKind::NotFound => std::io::Error::new(
+                            std::io::ErrorKind::NotFound,
+                            format!("Dangling symbolic link: {} -> {}", component, target),
+                        ),
+                        _ => e,
+                    })?;
             }
         }
+
+        Ok(current)
     }
 
-    fn extract_parent_paths(path: &str) -> String {
-        let path = Path::new(path);
-        match path.parent() {
-            Some(parent) => parent.to_str().unwrap_or("").to_string(),
-            None => "".to_string(),
+    /// Resolves everything but the last component of a path, returning the
+    /// containing directory and the final name. Fails with `NotFound` when
+    /// the parent doesn't exist and `NotADirectory` when it isn't a
+    /// directory, so nothing can be created outside the tree.
+    fn lookup_parent(&self, name: &str) -> std::io::Result<(u64, String)> {
+        let last = name.trim_end_matches('/').rsplit('/').next();
+        if matches!(last, Some("." | "..")) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Invalid argument: '.' and '..' cannot be used here",
+            ));
+        }
+
+        let full_path = self.get_full_path(name);
+        let (parent_path, base) = full_path.rsplit_once('/').unwrap_or(("", &full_path));
+        if base.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Invalid file name",
+            ));
+        }
+
+        let parent_path = if parent_path.is_empty() {
+            "/"
+        } else {
+            parent_path
+        };
+        let parent = self.lookup(parent_path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("Parent directory {} not found", parent_path),
+            ),
+            _ => e,
+        })?;
+        if self.entry(parent)?.file_type != FileType::Directory {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotADirectory,
+                format!("{} is not a directory", parent_path),
+            ));
         }
+        Ok((parent, base.to_string()))
     }
 
     pub fn create_file(
@@ -97,77 +419,141 @@ impl FileSystem {
         name: &str,
         file_type: FileType,
         user: &str,
-        permission: Option<Vec<FilePermission>>,
+        group: &str,
+        mode: u16,
     ) -> std::io::Result<()> {
-        let full_path = self.get_full_path(name);
+        if name.ends_with('/') && file_type != FileType::Directory {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotADirectory,
+                "Not a directory",
+            ));
+        }
+        let (parent, base) = self.lookup_parent(name)?;
 
-        if self.files.contains_key(&full_path) {
+        if self.entry(parent)?.entries.contains_key(&base) {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::AlreadyExists,
                 "File or Directory already exists",
             ));
         }
+        let parent_entry = self.entry(parent)?;
+        self.check_access(parent_entry, W_OK | X_OK)?;
 
-        let parent_path = if name.starts_with("/") {
-            Some(FileSystem::extract_parent_paths(name))
-        } else {
-            if self.current_path == "/" {
-                Some(format!("/{}", FileSystem::extract_parent_paths(name)))
-            } else if name.contains("/") {
-                Some(format!(
-                    "{}/{}",
-                    self.current_path,
-                    FileSystem::extract_parent_paths(name)
-                ))
+        // In a setgid directory new entries take the directory's group, and
+        // subdirectories keep the bit
+        let (group, mode) = if parent_entry.mode & S_ISGID != 0 {
+            let mode = if file_type == FileType::Directory {
+                mode | S_ISGID
             } else {
-                Some(self.current_path.clone())
-            }
+                mode
+            };
+            (parent_entry.owned_group.clone(), mode)
+        } else {
+            (Some(group.to_string()), mode)
         };
 
+        let now = Timestamp::now();
         let entry = FileEntry {
-            name: full_path.clone(),
             file_type,
-            permission: permission.clone(),
+            mode,
             owned_user: Some(user.to_string()),
+            owned_group: group,
             size: 0,
             pages: Vec::new(),
-            parent: parent_path.clone(),
+            links: 1,
+            parent: (file_type == FileType::Directory).then_some(parent),
+            entries: BTreeMap::new(),
+            target: None,
+            atime: now,
+            mtime: now,
+            ctime: now,
+            btime: now,
         };
 
-        self.files.insert(full_path.clone(), entry);
-
-        // if let FileType::Directory = file_type {
-        //     self.files.insert(
-        //         format!("{}/.", full_path.clone()),
-        //         FileEntry {
-        //             name: ".".to_string(),
-        //             file_type: FileType::Directory,
-        //             permission: permission.clone(),
-        //             owned_user: Some(user.to_string()),
-        //             size: 0,
-        //             pages: Vec::new(),
-        //             parent: Some(full_path.clone()),
-        //         },
-        //     );
-
-        //     self.files.insert(
-        //         format!("{}/..", full_path),
-        //         FileEntry {
-        //             name: "..".to_string(),
-        //             file_type: FileType::Directory,
-        //             permission,
-        //             owned_user: Some(user.to_string()),
-        //             size: 0,
-        //             pages: Vec::new(),
-        //             parent: parent_path,
-        //         },
-        //     );
-        // }
+        let inode = self.next_inode;
+        self.next_inode += 1;
+        self.inodes.insert(inode, entry);
+        if let Some(dir) = self.inodes.get_mut(&parent) {
+            dir.entries.insert(base, inode);
+        }
+        self.mark_modified(parent);
+
+        Ok(())
+    }
+
+    /// Records a change to the metadata of `inode`.
+    fn mark_changed(&mut self, inode: u64) {
+        if let Some(entry) = self.inodes.get_mut(&inode) {
+            entry.ctime = Timestamp::now();
+        }
+    }
+
+    /// Records a change to the data of `inode`, which for a directory are
+    /// its entries.
+    fn mark_modified(&mut self, inode: u64) {
+        if let Some(entry) = self.inodes.get_mut(&inode) {
+            let now = Timestamp::now();
+            entry.mtime = now;
+            entry.ctime = now;
+        }
+    }
+
+    /// Records a read of `inode`, as far as the atime policy asks for it.
+    fn mark_accessed(&mut self, inode: u64) {
+        let policy = self.atime_policy;
+        if let Some(entry) = self.inodes.get_mut(&inode) {
+            let now = Timestamp::now();
+            if policy.should_update(entry.atime, entry.mtime, entry.ctime, now) {
+                entry.atime = now;
+            }
+        }
+    }
+
+    pub fn atime_policy(&self) -> AtimePolicy {
+        self.atime_policy
+    }
+
+    /// Sets when reads update access times, like remounting with
+    /// `strictatime`, `relatime` or `noatime`.
+    pub fn set_atime_policy(&mut self, policy: AtimePolicy) {
+        self.atime_policy = policy;
+    }
+
+    /// Sets the access and/or modification time of `name` to `time`, or to
+    /// now if it is `None`. Like `utimensat`, moving them to now needs
+    /// ownership or write permission, but any other time needs ownership.
+    /// The change time becomes now.
+    pub fn set_times(
+        &mut self,
+        name: &str,
+        time: Option<Timestamp>,
+        access: bool,
+        modification: bool,
+    ) -> std::io::Result<()> {
+        self.check_capability(W_OK)?;
+        let inode = self.lookup(name)?;
+        let entry = self.entry(inode)?;
+        if time.is_some() {
+            self.check_owner(entry)?;
+        } else if self.check_owner(entry).is_err() {
+            self.check_access(entry, W_OK)?;
+        }
 
+        let now = Timestamp::now();
+        let time = time.unwrap_or(now);
+        if let Some(entry) = self.inodes.get_mut(&inode) {
+            if access {
+                entry.atime = time;
+            }
+            if modification {
+                entry.mtime = time;
+            }
+            entry.ctime = now;
+        }
         Ok(())
     }
 
-    pub fn allocate_page(&mut self, size: u64, path: &str) -> std::io::Result<u64> {
+    pub fn allocate_page<P: AsRef<Path>>(&mut self, size: u64, path: P) -> std::io::Result<u64> {
         let offset = if let Some(free_offset) = self.free_list.pop_front() {
             free_offset
         } else {
@@ -182,280 +568,1339 @@ impl FileSystem {
         Ok(offset)
     }
 
-    pub fn write_file<P: AsRef<Path>>(
-        &mut self,
-        name: &str,
-        data: &[u8],
-        path: P,
-    ) -> std::io::Result<()> {
-        let full_path = self.get_full_path(name);
-        let page_size = 1024;
-
-        let entry = self.files.get(&full_path);
-        let entry_owned_user = entry.unwrap().owned_user.clone();
-
-        if self.current_user.get_user_name() != "root" {
-            // Check if the entry_owned_user is not None and unwrap safely
-            if let Some(owned_user) = entry_owned_user {
-                if owned_user != self.current_user.get_user_name() {
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::PermissionDenied,
-                        "Permission denied",
-                    ));
-                }
-            } else {
-                // Handle the case where no user is set as the owner, if necessary
-                // This could be an error or a warning depending on your design
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::PermissionDenied,
-                    "No owner set for this file/directory",
-                ));
-            }
-        }
-
-        if let Some(entry) = self.files.get(&full_path) {
-            let mut pages = entry.pages.clone();
-            let mut size = entry.size;
-
-            let mut file = OpenOptions::new().write(true).open(&path)?;
-            let mut data_written = 0;
-
-            while data_written < data.len() {
-                if size % page_size == 0 {
-                    let new_page_offset =
-                        self.allocate_page(page_size as u64, path.as_ref().to_str().unwrap())?;
-                    pages.push(new_page_offset);
-                }
-
-                let current_page = pages.last().unwrap();
-                let offset_in_page = size % page_size;
-                let write_size = std::cmp::min(
-                    page_size - offset_in_page,
-                    (data.len() - data_written) as u64,
-                );
+    fn is_root(&self) -> bool {
+        self.current_user.get_user_name() == "root"
+    }
 
-                file.seek(SeekFrom::Start(*current_page + offset_in_page as u64))?;
-                file.write_all(&data[data_written..data_written + write_size as usize])?;
+    /// Makes `user` the user every following operation is checked against,
+    /// with only its primary group until `set_groups` is called.
+    pub fn set_user(&mut self, user: User) {
+        self.groups = vec![user.get_group_name()];
+        self.current_user = user;
+    }
 
-                size += write_size;
-                data_written += write_size as usize;
-            }
+    /// Sets the groups the current user belongs to, primary group first.
+    pub fn set_groups(&mut self, groups: Vec<String>) {
+        self.groups = groups;
+    }
 
-            if let Some(entry) = self.files.get_mut(&full_path) {
-                entry.pages = pages;
-                entry.size = size;
-            }
+    fn in_group(&self, group: Option<&str>) -> bool {
+        group.is_some_and(|group| self.groups.iter().any(|g| g == group))
+    }
 
+    /// Checks the capability flags of the current user, which apply even to
+    /// root. `R_OK` needs `can_read` and covers reading files and listing
+    /// directories, `W_OK` needs `can_write` and covers every change to the
+    /// tree or to file data and metadata, and `X_OK` needs `can_execute` and
+    /// covers running scripts.
+    fn check_capability(&self, access: u16) -> std::io::Result<()> {
+        let permissions = &self.current_user.permissions;
+        let allowed = (access & R_OK == 0 || permissions.can_read)
+            && (access & W_OK == 0 || permissions.can_write)
+            && (access & X_OK == 0 || permissions.can_execute);
+        if allowed {
             Ok(())
         } else {
             Err(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "File not found",
+                std::io::ErrorKind::PermissionDenied,
+                "Permission denied",
             ))
         }
     }
 
-    pub fn read_file<P: AsRef<Path>>(&self, name: &str, path: P) -> std::io::Result<Vec<u8>> {
-        let full_path = self.get_full_path(name);
-        if let Some(entry) = self.files.get(&full_path) {
-            let page_size = 1024;
-            let mut file = OpenOptions::new().read(true).open(path)?;
-            let mut buffer = Vec::with_capacity(entry.size as usize);
-
-            for (i, page_offset) in entry.pages.iter().enumerate() {
-                let mut page_buffer = vec![0; page_size];
-                file.seek(SeekFrom::Start(*page_offset))?;
-                let bytes_to_read = if i == entry.pages.len() - 1 {
-                    (entry.size % page_size as u64) as usize
-                } else {
-                    page_size
-                };
-
-                file.read_exact(&mut page_buffer[..bytes_to_read])?;
-                buffer.extend_from_slice(&page_buffer[..bytes_to_read]);
-            }
+    /// Checks `access`, a combination of `R_OK`, `W_OK` and `X_OK`, against
+    /// the owner bits of `entry` if the current user owns it, the group bits
+    /// if the user is in its group and the other bits otherwise. root is
+    /// never denied by the mode.
+    ///
+    /// Reading and writing also need the matching capability. Searching a
+    /// directory doesn't, so `X_OK` is left to `check_executable`.
+    fn check_access(&self, entry: &FileEntry, access: u16) -> std::io::Result<()> {
+        self.check_capability(access & (R_OK | W_OK))?;
+        if self.is_root() {
+            return Ok(());
+        }
 
-            Ok(buffer)
+        let bits = if entry.owned_user.as_deref() == Some(&self.current_user.get_user_name()) {
+            entry.mode >> 6
+        } else if self.in_group(entry.owned_group.as_deref()) {
+            entry.mode >> 3
+        } else {
+            entry.mode
+        };
+        if bits & access == access {
+            Ok(())
         } else {
             Err(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "File not found",
+                std::io::ErrorKind::PermissionDenied,
+                "Permission denied",
             ))
         }
     }
 
-    pub fn ls(&mut self) {
-        for (name, entry) in &self.files {
-            if entry.parent.as_deref() == Some(&self.current_path) {
-                let suffix = match entry.file_type {
-                    FileType::Directory => "/",
-                    FileType::File => "",
-                };
-                let color = match entry.file_type {
-                    FileType::Directory => "\x1b[34m",
-                    FileType::File => "\x1b[33m", // yellow
-                };
-                let white = "\x1b[0m";
-                self.stdio
-                    .write(format!("{}{:?}{} {}{}\n", color, entry.file_type, white, name, suffix).as_bytes());
-            }
+    /// Only root and the owner may change the metadata of `entry`.
+    fn check_owner(&self, entry: &FileEntry) -> std::io::Result<()> {
+        if self.is_root() || entry.owned_user.as_deref() == Some(&self.current_user.get_user_name())
+        {
+            Ok(())
+        } else {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Operation not permitted",
+            ))
         }
-        self.stdio.print();
     }
 
-    pub fn cd(&mut self, path: &str) -> std::io::Result<()> {
-        let full_path = self.get_full_path(path);
-        if path == "." {
-            return Ok(());
-        } else if path == ".." {
-            if let Some(parent_path) = self
-                .files
-                .get(&self.current_path)
-                .and_then(|entry| entry.parent.clone())
-            {
-                self.current_path = parent_path;
-                return Ok(());
-            } else {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::NotFound,
-                    "No parent directory",
-                ));
-            }
-        } else if let Some(entry) = self.files.get(&full_path) {
-            if let FileType::Directory = entry.file_type {
-                self.current_path = full_path;
-                return Ok(());
-            } else {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "Not a directory",
-                ));
-            }
-        } else {
+    /// Checks that the current user may run `name` as a script.
+    pub fn check_executable(&self, name: &str) -> std::io::Result<()> {
+        self.check_capability(X_OK)?;
+        let entry = self.entry(self.lookup(name)?)?;
+        if entry.file_type == FileType::Directory {
             return Err(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "Directory not found",
+                std::io::ErrorKind::IsADirectory,
+                "Is a directory",
+            ));
+        }
+        // Even root can only run files with an execute bit set
+        if entry.mode & 0o111 == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Permission denied",
             ));
         }
+        self.check_access(entry, X_OK)
     }
 
-    pub fn touch(&mut self, name: &str) -> std::io::Result<()> {
-        self.create_file(
-            name,
-            FileType::File,
-            self.current_user.get_user_name().as_str(),
-            Some(vec![FilePermission::Readable, FilePermission::Writable]),
-        )
+    /// Looks up a regular file the current user may write to.
+    fn writable_file(&self, inode: u64) -> std::io::Result<&FileEntry> {
+        let entry = self.entry(inode)?;
+        if entry.file_type == FileType::Directory {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::IsADirectory,
+                "Is a directory",
+            ));
+        }
+        self.check_access(entry, W_OK)?;
+        Ok(entry)
     }
 
-    pub fn mkdir(&mut self, name: &str) -> std::io::Result<()> {
-        self.create_file(
-            name,
-            FileType::Directory,
-            self.current_user.get_user_name().as_str(),
-            Some(vec![FilePermission::Readable, FilePermission::Writable]),
-        )
+    /// Sets the size of a file to `len` bytes.
+    ///
+    /// Shrinking gives whole pages past the new end back to the free list and
+    /// zeroes the rest of the last page, so that growing the file again reads
+    /// zeros. Growing leaves a hole, which takes up no pages until written.
+    pub fn truncate<P: AsRef<Path>>(
+        &mut self,
+        name: &str,
+        len: u64,
+        path: P,
+    ) -> std::io::Result<()> {
+        let inode = self.lookup(name)?;
+        self.truncate_inode(inode, len, path)
     }
 
-    // pub fn _cat(&self, name: &str, path: &str) {
-    //     match self.read_file(name, path) {
-    //         Ok(data) => match String::from_utf8(data) {
-    //             Ok(content) => println!("{}", content),
-    //             Err(e) => eprintln!("Error converting file content to string: {}", e),
-    //         },
-    //         Err(e) => eprintln!("Error reading file {}: {}", name, e),
-    //     }
-    // }
+    fn truncate_inode<P: AsRef<Path>>(
+        &mut self,
+        inode: u64,
+        len: u64,
+        path: P,
+    ) -> std::io::Result<()> {
+        let entry = self.writable_file(inode)?;
+        if len > MAX_FILE_SIZE {
+            return Err(file_too_large());
+        }
+        let mut pages = entry.pages.clone();
+        let size = entry.size;
 
-    pub fn rm(&mut self, name: &str) -> std::io::Result<()> {
-        let full_path = self.get_full_path(name);
-        if let Some(entry) = self.files.remove(&full_path) {
-            if entry.file_type == FileType::File {
-                if self.current_user.get_user_name() != "root"
-                    && entry.owned_user.unwrap() != self.current_user.get_user_name()
-                {
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::PermissionDenied,
-                        "Permission denied",
-                    ));
-                }
+        let pages_needed = len.div_ceil(PAGE_SIZE) as usize;
+        if len < size {
+            if pages.len() > pages_needed {
+                self.free_list.extend(pages.drain(pages_needed..).flatten());
+            }
 
-                for page in entry.pages {
-                    self.free_list.push_back(page);
-                }
-            } else if entry.file_type == FileType::Directory {
-                let prefix = format!("{}/", full_path);
-                let to_remove: Vec<_> = self
-                    .files
-                    .keys()
-                    .filter(|k| k.starts_with(&prefix))
-                    .cloned()
-                    .collect();
-                for key in to_remove {
-                    self.rm(&key)?;
+            let offset_in_page = len % PAGE_SIZE;
+            if offset_in_page != 0 {
+                if let Some(Some(page)) = pages.get(pages_needed - 1) {
+                    let mut file = OpenOptions::new().write(true).open(&path)?;
+                    file.seek(SeekFrom::Start(page + offset_in_page))?;
+                    file.write_all(&vec![0; (PAGE_SIZE - offset_in_page) as usize])?;
                 }
             }
-            Ok(())
-        } else {
-            Err(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "File or directory not found",
-            ))
         }
+
+        if let Some(entry) = self.inodes.get_mut(&inode) {
+            entry.pages = pages;
+            entry.size = len;
+        }
+        self.mark_modified(inode);
+        Ok(())
     }
 
-    pub fn cp<P: AsRef<Path>>(
+    /// Writes `data` at `offset`, overwriting whatever is there.
+    ///
+    /// Writing past the end of the file extends it; any gap between the old
+    /// end and `offset` reads back as zeros.
+    pub fn write_at<P: AsRef<Path>>(
         &mut self,
-        src_name: &str,
-        dest_name: &str,
+        name: &str,
+        offset: u64,
+        data: &[u8],
         path: P,
     ) -> std::io::Result<()> {
-        let src_full_path = self.get_full_path(src_name);
-        // Get the parent path of the destination file from the full path
-
-        let (data, user, permissions) = {
-            if let Some(src_entry) = self.files.get(&src_full_path) {
-                (
-                    self.read_file(src_name, &path)?,
-                    <std::option::Option<std::string::String> as Clone>::clone(
-                        &src_entry.owned_user,
-                    )
-                    .unwrap()
-                    .to_string(),
-                    src_entry.permission.clone(),
-                )
-            } else {
-                return Err(std::io::Error::new(
-                    std::io::ErrorKind::NotFound,
-                    "Source file not found",
-                ));
+        let inode = self.lookup(name)?;
+        self.write_inode(inode, offset, data, path)
+    }
+
+    fn write_inode<P: AsRef<Path>>(
+        &mut self,
+        inode: u64,
+        offset: u64,
+        data: &[u8],
+        path: P,
+    ) -> std::io::Result<()> {
+        let entry = self.writable_file(inode)?;
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        let end = offset
+            .checked_add(data.len() as u64)
+            .filter(|&end| end <= MAX_FILE_SIZE)
+            .ok_or_else(file_too_large)?;
+        let mut pages = entry.pages.clone();
+        let size = std::cmp::max(entry.size, end);
+
+        // Only the pages the data lands in are allocated; any gap before
+        // them stays a hole
+        let first_page = (offset / PAGE_SIZE) as usize;
+        let last_page = end.div_ceil(PAGE_SIZE) as usize;
+        if pages.len() < last_page {
+            pages.resize(last_page, None);
+        }
+        for page in &mut pages[first_page..last_page] {
+            if page.is_none() {
+                *page = Some(self.allocate_page(PAGE_SIZE, &path)?);
             }
-        };
+        }
 
-        self.create_file(dest_name, FileType::File, user.as_str(), permissions)?;
-        self.write_file(dest_name, &data, path)?;
-        Ok(())
-    }
+        let mut file = OpenOptions::new().write(true).open(&path)?;
+        let mut data_written = 0;
 
-    pub fn mv<P: AsRef<Path>>(&mut self, src_name: &str, dest_name: &str, path: P) -> std::io::Result<()> {
-        self.cp(src_name, dest_name, path)?;
-        self.rm(src_name)
-    }
+        while data_written < data.len() {
+            let position = offset + data_written as u64;
+            let current_page = pages[(position / PAGE_SIZE) as usize].unwrap_or_default();
+            let offset_in_page = position % PAGE_SIZE;
+            let write_size = std::cmp::min(
+                PAGE_SIZE - offset_in_page,
+                (data.len() - data_written) as u64,
+            ) as usize;
 
-    pub fn is_file_exists(&self, name: &str) -> bool {
-        self.files.contains_key(&self.get_full_path(name))
-    }
+            file.seek(SeekFrom::Start(current_page + offset_in_page))?;
+            file.write_all(&data[data_written..data_written + write_size])?;
+
+            data_written += write_size;
+        }
+
+        if let Some(entry) = self.inodes.get_mut(&inode) {
+            entry.pages = pages;
+            entry.size = size;
+        }
+        self.mark_modified(inode);
 
-    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
-        let file = OpenOptions::new().write(true).truncate(true).open(path)?;
-        serde_json::to_writer(file, self)?;
         Ok(())
     }
 
-    pub fn load<P: AsRef<Path>>(path: P, user: User) -> std::io::Result<Self> {
-        let file = File::open(path)?;
-        let mut fs: FileSystem = serde_json::from_reader(file)?;
-        fs.current_user = user;
-        Ok(fs)
+    /// Appends `data` to the end of a file.
+    pub fn write_file<P: AsRef<Path>>(
+        &mut self,
+        name: &str,
+        data: &[u8],
+        path: P,
+    ) -> std::io::Result<()> {
+        let size = self.entry(self.lookup(name)?)?.size;
+        self.write_at(name, size, data, path)
+    }
+
+    /// Reads up to `len` bytes starting at `offset`, touching only the pages
+    /// that hold them.
+    fn read_at<P: AsRef<Path>>(
+        &self,
+        entry: &FileEntry,
+        offset: u64,
+        len: u64,
+        path: P,
+    ) -> std::io::Result<Vec<u8>> {
+        let end = std::cmp::min(entry.size, offset.saturating_add(len));
+        if offset >= end {
+            return Ok(Vec::new());
+        }
+
+        let mut file = OpenOptions::new().read(true).open(path)?;
+        let mut buffer = vec![0; (end - offset) as usize];
+        let mut data_read = 0;
+
+        while data_read < buffer.len() {
+            let position = offset + data_read as u64;
+            let current_page = entry.pages.get((position / PAGE_SIZE) as usize);
+            let offset_in_page = position % PAGE_SIZE;
+            let read_size = std::cmp::min(
+                PAGE_SIZE - offset_in_page,
+                (buffer.len() - data_read) as u64,
+            ) as usize;
+
+            // Holes are already zero in the buffer
+            if let Some(&Some(current_page)) = current_page {
+                file.seek(SeekFrom::Start(current_page + offset_in_page))?;
+                file.read_exact(&mut buffer[data_read..data_read + read_size])?;
+            }
+
+            data_read += read_size;
+        }
+
+        Ok(buffer)
+    }
+
+    pub fn read_file<P: AsRef<Path>>(&mut self, name: &str, path: P) -> std::io::Result<Vec<u8>> {
+        let inode = self.lookup(name)?;
+        let entry = self.entry(inode)?;
+        if entry.file_type == FileType::Directory {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::IsADirectory,
+                "Is a directory",
+            ));
+        }
+        self.check_access(entry, R_OK)?;
+        let data = self.read_at(entry, 0, entry.size, path)?;
+        self.mark_accessed(inode);
+        Ok(data)
+    }
+
+    /// Opens a file and returns a descriptor for `read`, `write` and `lseek`.
+    ///
+    /// `flags` combine one of `O_RDONLY`, `O_WRONLY` and `O_RDWR` with
+    /// `O_CREAT`, `O_TRUNC` and `O_APPEND`. Data goes through the container
+    /// at `path`, which the descriptor remembers until it is closed.
+    pub fn open<P: AsRef<Path>>(
+        &mut self,
+        name: &str,
+        flags: u32,
+        path: P,
+    ) -> std::io::Result<u32> {
+        let inode = match self.lookup(name) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && flags & O_CREAT != 0 => {
+                self.touch(name)?;
+                self.lookup(name)?
+            }
+            result => result?,
+        };
+        if self.entry(inode)?.file_type == FileType::Directory {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::IsADirectory,
+                "Is a directory",
+            ));
+        }
+
+        let file = OpenFile {
+            inode,
+            flags,
+            offset: 0,
+            container: path.as_ref().to_path_buf(),
+        };
+        if file.is_readable() {
+            self.check_access(self.entry(inode)?, R_OK)?;
+        }
+        if file.is_writable() {
+            self.writable_file(inode)?;
+            if flags & O_TRUNC != 0 {
+                self.truncate_inode(inode, 0, &path)?;
+            }
+        }
+
+        let fd = (FIRST_FD..)
+            .find(|fd| !self.open_files.contains_key(fd))
+            .unwrap();
+        self.open_files.insert(fd, file);
+        Ok(fd)
+    }
+
+    fn open_file(&self, fd: u32) -> std::io::Result<&OpenFile> {
+        self.open_files.get(&fd).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "Bad file descriptor")
+        })
+    }
+
+    /// Reads up to `len` bytes from the descriptor's offset and advances it.
+    /// An empty result means end of file.
+    pub fn read(&mut self, fd: u32, len: u64) -> std::io::Result<Vec<u8>> {
+        let file = self.open_file(fd)?;
+        if !file.is_readable() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "File descriptor is not open for reading",
+            ));
+        }
+
+        let inode = file.inode;
+        let entry = self.entry(inode)?;
+        let data = self.read_at(entry, file.offset, len, &file.container)?;
+
+        if let Some(file) = self.open_files.get_mut(&fd) {
+            file.offset += data.len() as u64;
+        }
+        self.mark_accessed(inode);
+        Ok(data)
+    }
+
+    /// Writes `data` at the descriptor's offset, or at the end of the file
+    /// with `O_APPEND`, and advances the offset past it.
+    pub fn write(&mut self, fd: u32, data: &[u8]) -> std::io::Result<usize> {
+        let file = self.open_file(fd)?.clone();
+        if !file.is_writable() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "File descriptor is not open for writing",
+            ));
+        }
+
+        let offset = if file.flags & O_APPEND != 0 {
+            self.writable_file(file.inode)?.size
+        } else {
+            file.offset
+        };
+        self.write_inode(file.inode, offset, data, &file.container)?;
+
+        if let Some(file) = self.open_files.get_mut(&fd) {
+            file.offset = offset + data.len() as u64;
+        }
+        Ok(data.len())
+    }
+
+    /// Moves the descriptor's offset and returns the new position. Seeking
+    /// past the end is allowed; a later write leaves a hole.
+    pub fn lseek(&mut self, fd: u32, pos: SeekFrom) -> std::io::Result<u64> {
+        let file = self.open_file(fd)?;
+        let size = self.entry(file.inode)?.size;
+
+        let offset = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => file.offset.checked_add_signed(delta),
+            SeekFrom::End(delta) => size.checked_add_signed(delta),
+        }
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid offset"))?;
+
+        if let Some(file) = self.open_files.get_mut(&fd) {
+            file.offset = offset;
+        }
+        Ok(offset)
+    }
+
+    pub fn close(&mut self, fd: u32) -> std::io::Result<()> {
+        self.open_files.remove(&fd).map(|_| ()).ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "Bad file descriptor")
+        })
+    }
+
+    /// The entries of the directory `name` in name order, starting with `.`
+    /// and `..`. Listing a directory needs read permission on it.
+    pub fn read_dir(&mut self, name: &str) -> std::io::Result<Vec<DirEntry>> {
+        let inode = self.lookup(name)?;
+        let entry = self.entry(inode)?;
+        if entry.file_type != FileType::Directory {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotADirectory,
+                "Not a directory",
+            ));
+        }
+        self.check_access(entry, R_OK)?;
+
+        let mut entries = vec![
+            DirEntry {
+                name: ".".to_string(),
+                stat: self.stat_inode(inode)?,
+            },
+            DirEntry {
+                name: "..".to_string(),
+                stat: self.stat_inode(entry.parent.unwrap_or(inode))?,
+            },
+        ];
+        for (name, &child) in &entry.entries {
+            entries.push(DirEntry {
+                name: name.clone(),
+                stat: self.stat_inode(child)?,
+            });
+        }
+        self.mark_accessed(inode);
+        Ok(entries)
+    }
+
+    pub fn cd(&mut self, path: &str) -> std::io::Result<()> {
+        let inode = self.lookup(path).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => {
+                std::io::Error::new(std::io::ErrorKind::NotFound, "Directory not found")
+            }
+            _ => e,
+        })?;
+        let entry = self.entry(inode)?;
+        if let FileType::Directory = entry.file_type {
+            self.check_access(entry, X_OK)?;
+            self.cwd = inode;
+            Ok(())
+        } else {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::NotADirectory,
+                "Not a directory",
+            ))
+        }
+    }
+
+    pub fn touch(&mut self, name: &str) -> std::io::Result<()> {
+        self.create_file(
+            name,
+            FileType::File,
+            self.current_user.get_user_name().as_str(),
+            self.current_user.get_group_name().as_str(),
+            0o666 & !UMASK,
+        )
+    }
+
+    pub fn mkdir(&mut self, name: &str) -> std::io::Result<()> {
+        self.create_file(
+            name,
+            FileType::Directory,
+            self.current_user.get_user_name().as_str(),
+            self.current_user.get_group_name().as_str(),
+            0o777 & !UMASK,
+        )
+    }
+
+    /// Creates a directory along with any missing parents, like `mkdir -p`.
+    /// A directory that already exists is not an error.
+    pub fn mkdir_all(&mut self, name: &str) -> std::io::Result<()> {
+        let full_path = self.get_full_path(name);
+        let mut path = String::new();
+
+        for component in full_path.split('/').filter(|c| !c.is_empty()) {
+            path.push('/');
+            path.push_str(component);
+            match self.lookup(&path) {
+                Ok(inode) if self.entry(inode)?.file_type == FileType::Directory => {}
+                Ok(_) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::NotADirectory,
+                        format!("{} is not a directory", path),
+                    ))
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => self.mkdir(&path)?,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    // pub fn _cat(&self, name: &str, path: &str) {
+    //     match self.read_file(name, path) {
+    //         Ok(data) => match String::from_utf8(data) {
+    //             Ok(content) => println!("{}", content),
+    //             Err(e) => eprintln!("Error converting file content to string: {}", e),
+    //         },
+    //         Err(e) => eprintln!("Error reading file {}: {}", name, e),
+    //     }
+    // }
+
+    /// Removes the file `name`. Directories need `rmdir` or `rm_all`.
+    pub fn rm(&mut self, name: &str) -> std::io::Result<()> {
+        let (parent, base) = self.lookup_parent(name)?;
+        let inode = self.child(parent, &base)?;
+        if self.entry(inode)?.file_type == FileType::Directory {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::IsADirectory,
+                "Is a directory",
+            ));
+        }
+        self.check_unlink(parent, inode)?;
+        self.unlink(parent, &base, inode);
+        Ok(())
+    }
+
+    /// Removes the directory `name`, which has to be empty.
+    pub fn rmdir(&mut self, name: &str) -> std::io::Result<()> {
+        let (parent, base) = self.lookup_parent(name)?;
+        let inode = self.child(parent, &base)?;
+        let entry = self.entry(inode)?;
+        if entry.file_type != FileType::Directory {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotADirectory,
+                "Not a directory",
+            ));
+        }
+        if !entry.entries.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::DirectoryNotEmpty,
+                "Directory not empty",
+            ));
+        }
+        self.check_unlink(parent, inode)?;
+        self.unlink(parent, &base, inode);
+        Ok(())
+    }
+
+    /// Removes `name`, and a directory with everything below it, like
+    /// `rm -r`. Nothing is removed unless the current user may remove all of
+    /// it. Removing `/` empties it, since the root itself can't go.
+    pub fn rm_all(&mut self, name: &str) -> std::io::Result<()> {
+        if self.lookup_nofollow(name)? == ROOT_INODE {
+            self.check_remove_tree(ROOT_INODE)?;
+            let names: Vec<String> = self.entry(ROOT_INODE)?.entries.keys().cloned().collect();
+            for name in names {
+                self.rm_all(&format!("/{}", name))?;
+            }
+            return Ok(());
+        }
+
+        let (parent, base) = self.lookup_parent(name)?;
+        let inode = self.child(parent, &base)?;
+        self.check_unlink(parent, inode)?;
+        self.check_remove_tree(inode)?;
+        self.unlink(parent, &base, inode);
+        Ok(())
+    }
+
+    /// Whether the current directory is `name` or lies somewhere below it,
+    /// so that removing `name` would take it away.
+    pub fn is_cwd_within(&self, name: &str) -> bool {
+        self.lookup_nofollow(name)
+            .is_ok_and(|inode| self.is_within(self.cwd, inode).unwrap_or(false))
+    }
+
+    /// Takes the entry `base` of `inode` out of the directory `parent`. If
+    /// the current directory goes with it, `/` becomes the current directory.
+    fn unlink(&mut self, parent: u64, base: &str, inode: u64) {
+        if let Some(dir) = self.inodes.get_mut(&parent) {
+            dir.entries.remove(base);
+        }
+        self.mark_modified(parent);
+        self.remove_inode(inode);
+        if !self.inodes.contains_key(&self.cwd) {
+            self.cwd = ROOT_INODE;
+        }
+    }
+
+    /// Checks that the current user may remove or rename the entry for
+    /// `inode` in the directory `dir`. That takes write and search
+    /// permission on `dir`, and in a sticky directory like `/tmp` also
+    /// owning either the entry or the directory.
+    fn check_unlink(&self, dir: u64, inode: u64) -> std::io::Result<()> {
+        let dir_entry = self.entry(dir)?;
+        self.check_access(dir_entry, W_OK | X_OK)?;
+
+        let user = self.current_user.get_user_name();
+        if dir_entry.mode & S_ISVTX != 0
+            && !self.is_root()
+            && dir_entry.owned_user.as_deref() != Some(&user)
+            && self.entry(inode)?.owned_user.as_deref() != Some(&user)
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Operation not permitted",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks that everything inside the directory `inode` may be removed,
+    /// which needs read permission on each non-empty directory as well.
+    fn check_remove_tree(&self, inode: u64) -> std::io::Result<()> {
+        let entry = self.entry(inode)?;
+        if entry.file_type != FileType::Directory || entry.entries.is_empty() {
+            return Ok(());
+        }
+        self.check_access(entry, R_OK)?;
+        for &child in entry.entries.values() {
+            self.check_unlink(inode, child)?;
+            self.check_remove_tree(child)?;
+        }
+        Ok(())
+    }
+
+    /// Drops one link to an inode. A file's pages go back to the free list
+    /// once its last link is gone.
+    fn remove_inode(&mut self, inode: u64) {
+        let Some(entry) = self.inodes.get_mut(&inode) else {
+            return;
+        };
+
+        if entry.file_type != FileType::Directory {
+            entry.links -= 1;
+            entry.ctime = Timestamp::now();
+            if entry.links == 0 {
+                if let Some(entry) = self.inodes.remove(&inode) {
+                    self.free_list.extend(entry.pages.into_iter().flatten());
+                }
+            }
+        } else if let Some(entry) = self.inodes.remove(&inode) {
+            for child in entry.entries.into_values() {
+                self.remove_inode(child);
+            }
+        }
+    }
+
+    /// Creates `link_name` as another name for the file `target`.
+    pub fn link(&mut self, target: &str, link_name: &str) -> std::io::Result<()> {
+        let inode = self.lookup_nofollow(target)?;
+        if self.entry(inode)?.file_type == FileType::Directory {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Hard link not allowed for directory",
+            ));
+        }
+
+        let (parent, base) = self.lookup_parent(link_name)?;
+        if self.entry(parent)?.entries.contains_key(&base) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "File or Directory already exists",
+            ));
+        }
+        self.check_access(self.entry(parent)?, W_OK | X_OK)?;
+
+        if let Some(dir) = self.inodes.get_mut(&parent) {
+            dir.entries.insert(base, inode);
+        }
+        if let Some(entry) = self.inodes.get_mut(&inode) {
+            entry.links += 1;
+        }
+        self.mark_modified(parent);
+        self.mark_changed(inode);
+        Ok(())
+    }
+
+    /// Creates a symbolic link at `link_name` pointing to `target`. The
+    /// target is stored as given and doesn't have to exist.
+    pub fn symlink(&mut self, target: &str, link_name: &str) -> std::io::Result<()> {
+        self.create_file(
+            link_name,
+            FileType::Symlink,
+            self.current_user.get_user_name().as_str(),
+            self.current_user.get_group_name().as_str(),
+            0o777,
+        )?;
+
+        let inode = self.lookup_nofollow(link_name)?;
+        if let Some(entry) = self.inodes.get_mut(&inode) {
+            entry.size = target.len() as u64;
+            entry.target = Some(target.to_string());
+        }
+        Ok(())
+    }
+
+    /// Returns `inode` followed by, if `recursive`, every inode below it.
+    /// Symbolic links inside the tree are listed but not followed.
+    fn subtree(&self, inode: u64, recursive: bool) -> std::io::Result<Vec<u64>> {
+        let mut inodes = vec![inode];
+        let mut next = 0;
+        while recursive && next < inodes.len() {
+            let entry = self.entry(inodes[next])?;
+            if entry.file_type == FileType::Directory {
+                inodes.extend(entry.entries.values());
+            }
+            next += 1;
+        }
+        Ok(inodes)
+    }
+
+    /// Changes the mode of `name`, and with `recursive` of everything below
+    /// it. Nothing changes unless the current user owns every file.
+    pub fn chmod(
+        &mut self,
+        name: &str,
+        change: &ModeChange,
+        recursive: bool,
+    ) -> std::io::Result<()> {
+        self.check_capability(W_OK)?;
+        let inodes = self.subtree(self.lookup(name)?, recursive)?;
+        for inode in &inodes {
+            self.check_owner(self.entry(*inode)?)?;
+        }
+
+        for inode in inodes {
+            if let Some(entry) = self.inodes.get_mut(&inode) {
+                // The mode of a symbolic link is never used
+                if entry.file_type != FileType::Symlink {
+                    entry.mode = change.apply(entry.mode, entry.file_type == FileType::Directory);
+                    entry.ctime = Timestamp::now();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Changes the owning user and/or group of `name`, and with `recursive`
+    /// of everything below it. Only root may give files to another user;
+    /// owners may change the group of their own files to one of their groups.
+    pub fn chown(
+        &mut self,
+        name: &str,
+        user: Option<&str>,
+        group: Option<&str>,
+        recursive: bool,
+    ) -> std::io::Result<()> {
+        self.check_capability(W_OK)?;
+        let inodes = self.subtree(self.lookup(name)?, recursive)?;
+        for inode in &inodes {
+            let entry = self.entry(*inode)?;
+            if user.is_some_and(|user| entry.owned_user.as_deref() != Some(user)) && !self.is_root()
+            {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    "Operation not permitted",
+                ));
+            }
+            self.check_owner(entry)?;
+        }
+        if group.is_some() && !self.is_root() && !self.in_group(group) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Operation not permitted",
+            ));
+        }
+
+        for inode in inodes {
+            if let Some(entry) = self.inodes.get_mut(&inode) {
+                if let Some(user) = user {
+                    entry.owned_user = Some(user.to_string());
+                }
+                if let Some(group) = group {
+                    entry.owned_group = Some(group.to_string());
+                }
+                entry.ctime = Timestamp::now();
+            }
+        }
+        Ok(())
+    }
+
+    /// Paths of everything owned by `user`, a hard-linked file once per
+    /// name.
+    pub fn find_owned_by(&self, user: &str) -> Vec<String> {
+        let mut found = Vec::new();
+        let mut pending = vec![(ROOT_INODE, String::new())];
+        while let Some((dir, path)) = pending.pop() {
+            let Some(entry) = self.inodes.get(&dir) else {
+                continue;
+            };
+            for (name, &inode) in &entry.entries {
+                let child_path = format!("{}/{}", path, name);
+                let Some(child) = self.inodes.get(&inode) else {
+                    continue;
+                };
+                if child.owned_user.as_deref() == Some(user) {
+                    found.push(child_path.clone());
+                }
+                if child.file_type == FileType::Directory {
+                    pending.push((inode, child_path));
+                }
+            }
+        }
+        found.sort();
+        found
+    }
+
+    /// Metadata of `name`, following symbolic links.
+    pub fn stat(&self, name: &str) -> std::io::Result<Stat> {
+        self.stat_inode(self.lookup(name)?)
+    }
+
+    /// Metadata of `name` itself, even if it is a symbolic link.
+    pub fn lstat(&self, name: &str) -> std::io::Result<Stat> {
+        self.stat_inode(self.lookup_nofollow(name)?)
+    }
+
+    fn stat_inode(&self, inode: u64) -> std::io::Result<Stat> {
+        let entry = self.entry(inode)?;
+        let links = if entry.file_type == FileType::Directory {
+            let subdirectories = entry
+                .entries
+                .values()
+                .filter(|child| {
+                    self.inodes
+                        .get(child)
+                        .is_some_and(|child| child.file_type == FileType::Directory)
+                })
+                .count();
+            2 + subdirectories as u32
+        } else {
+            entry.links
+        };
+
+        Ok(Stat {
+            inode,
+            file_type: entry.file_type,
+            mode: entry.mode,
+            links,
+            owner: entry.owned_user.clone().unwrap_or_default(),
+            group: entry.owned_group.clone().unwrap_or_default(),
+            size: entry.size,
+            pages: entry.pages.iter().flatten().count() as u64,
+            atime: entry.atime,
+            mtime: entry.mtime,
+            ctime: entry.ctime,
+            btime: entry.btime,
+            target: entry.target.clone(),
+        })
+    }
+
+    pub fn readlink(&self, name: &str) -> std::io::Result<String> {
+        self.check_capability(R_OK)?;
+        self.entry(self.lookup_nofollow(name)?)?
+            .target
+            .clone()
+            .ok_or_else(|| {
+                std::io::Error::new(std::io::ErrorKind::InvalidInput, "Not a symbolic link")
+            })
+    }
+
+    /// Copies `src_name` to `dest_name`, or into it if it is a directory.
+    /// Directories need `recursive`, which copies symbolic links as links
+    /// instead of following them. With `preserve` the copy keeps the mode and
+    /// times of the source, and as far as permitted its owner and group.
+    pub fn cp<P: AsRef<Path>>(
+        &mut self,
+        src_name: &str,
+        dest_name: &str,
+        recursive: bool,
+        preserve: bool,
+        path: P,
+    ) -> std::io::Result<()> {
+        let src_inode = if recursive {
+            self.lookup_nofollow(src_name)
+        } else {
+            self.lookup(src_name)
+        };
+        let Ok(src_inode) = src_inode else {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "Source file not found",
+            ));
+        };
+        let dest_name = self.destination(src_name, dest_name);
+
+        if self.entry(src_inode)?.file_type == FileType::Directory {
+            if !recursive {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("-r not specified; omitting directory '{}'", src_name),
+                ));
+            }
+            let (dest_parent, _) = self.lookup_parent(&dest_name)?;
+            if self.is_within(dest_parent, src_inode)? {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "Cannot copy a directory into itself",
+                ));
+            }
+        }
+        self.copy_inode(src_inode, &dest_name, preserve, path.as_ref())
+    }
+
+    /// Copies the file `src_inode` to the new name `dest_name`, along with
+    /// everything below it if it is a directory.
+    fn copy_inode(
+        &mut self,
+        src_inode: u64,
+        dest_name: &str,
+        preserve: bool,
+        path: &Path,
+    ) -> std::io::Result<()> {
+        let src = self.entry(src_inode)?.clone();
+        let user = self.current_user.get_user_name();
+        let group = self.current_user.get_group_name();
+
+        // The copy belongs to whoever made it. It stays writable until the
+        // contents are in, so a read-only source still copies.
+        match src.file_type {
+            FileType::File => {
+                self.check_access(&src, R_OK)?;
+                let data = self.read_at(&src, 0, src.size, path)?;
+                self.mark_accessed(src_inode);
+                self.create_file(dest_name, FileType::File, &user, &group, 0o600)?;
+                self.write_file(dest_name, &data, path)?;
+            }
+            FileType::Directory => {
+                self.check_access(&src, R_OK | X_OK)?;
+                self.create_file(dest_name, FileType::Directory, &user, &group, 0o700)?;
+                self.mark_accessed(src_inode);
+                for (name, &child) in &src.entries {
+                    self.copy_inode(child, &format!("{}/{}", dest_name, name), preserve, path)?;
+                }
+            }
+            FileType::Symlink => {
+                self.symlink(src.target.as_deref().unwrap_or_default(), dest_name)?;
+            }
+        }
+
+        let inode = self.lookup_nofollow(dest_name)?;
+        let keep_owner = preserve && self.is_root();
+        let keep_group = preserve && (self.is_root() || self.in_group(src.owned_group.as_deref()));
+        let Some(entry) = self.inodes.get_mut(&inode) else {
+            return Ok(());
+        };
+        if src.file_type == FileType::Symlink {
+            return Ok(());
+        }
+        if preserve {
+            entry.mode = src.mode;
+            if keep_owner {
+                entry.owned_user = src.owned_user;
+            } else {
+                entry.mode &= !S_ISUID;
+            }
+            if keep_group {
+                entry.owned_group = src.owned_group;
+            } else {
+                entry.mode &= !S_ISGID;
+            }
+            entry.atime = src.atime;
+            entry.mtime = src.mtime;
+        } else {
+            entry.mode = src.mode & 0o777;
+        }
+        Ok(())
+    }
+
+    /// Where `cp` and `mv` put `src_name`: inside `dest_name` if that is an
+    /// existing directory, otherwise `dest_name` itself.
+    fn destination(&self, src_name: &str, dest_name: &str) -> String {
+        let is_dir = self
+            .lookup(dest_name)
+            .is_ok_and(|inode| self.inodes[&inode].file_type == FileType::Directory);
+        if !is_dir {
+            return dest_name.to_string();
+        }
+        let full_path = self.get_full_path(src_name);
+        let base = full_path.rsplit('/').next().unwrap_or_default();
+        format!("{}/{}", dest_name.trim_end_matches('/'), base)
+    }
+
+    /// Whether `inode` is the directory `dir` or lies somewhere below it.
+    fn is_within(&self, inode: u64, dir: u64) -> std::io::Result<bool> {
+        let mut ancestor = Some(inode);
+        while let Some(current) = ancestor {
+            if current == dir {
+                return Ok(true);
+            }
+            ancestor = self.entry(current)?.parent;
+        }
+        Ok(false)
+    }
+
+    /// Moves a directory entry to a new name, or into `dest_name` if it is a
+    /// directory. Only the two parent directories change, so moving a
+    /// directory keeps its whole subtree in place.
+    pub fn mv(&mut self, src_name: &str, dest_name: &str) -> std::io::Result<()> {
+        let (src_parent, src_base) = self.lookup_parent(src_name)?;
+        let inode = self.child(src_parent, &src_base)?;
+        let dest_name = self.destination(src_name, dest_name);
+        let (dest_parent, dest_base) = self.lookup_parent(&dest_name)?;
+
+        if self.entry(dest_parent)?.entries.contains_key(&dest_base) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "File or Directory already exists",
+            ));
+        }
+        self.check_unlink(src_parent, inode)?;
+        self.check_access(self.entry(dest_parent)?, W_OK | X_OK)?;
+
+        // A directory can't be moved into its own subtree
+        if self.is_within(dest_parent, inode)? {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Cannot move a directory into itself",
+            ));
+        }
+
+        if let Some(dir) = self.inodes.get_mut(&src_parent) {
+            dir.entries.remove(&src_base);
+        }
+        if let Some(dir) = self.inodes.get_mut(&dest_parent) {
+            dir.entries.insert(dest_base, inode);
+        }
+        if let Some(entry) = self.inodes.get_mut(&inode) {
+            if entry.file_type == FileType::Directory {
+                entry.parent = Some(dest_parent);
+            }
+        }
+        self.mark_modified(src_parent);
+        self.mark_modified(dest_parent);
+        self.mark_changed(inode);
+        Ok(())
+    }
+
+    pub fn is_file_exists(&self, name: &str) -> bool {
+        self.lookup(name).is_ok()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let file = OpenOptions::new().write(true).truncate(true).open(path)?;
+        serde_json::to_writer(file, self)?;
+        Ok(())
+    }
+
+    /// Reads a filesystem saved by `save`. A file from before the inode
+    /// table, which kept a `files` map keyed by path, is refused.
+    pub fn load<P: AsRef<Path>>(path: P, user: User) -> std::io::Result<Self> {
+        let file = File::open(&path)?;
+        let value: serde_json::Value = serde_json::from_reader(file)?;
+        if value.get("files").is_some() && value.get("inodes").is_none() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "Incompatible {}: it was written by an older version without \
+                     inodes; move it away to start with a new filesystem",
+                    path.as_ref().display()
+                ),
+            ));
+        }
+        let mut fs: FileSystem = serde_json::from_value(value)?;
+        fs.groups = vec![user.get_group_name()];
+        fs.current_user = user;
+        Ok(fs)
+    }
+}
+
+fn file_too_large() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::FileTooLarge, "File too large")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        path::PathBuf,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    /// An empty `container.bin` in the temporary directory, removed on drop.
+    struct Container(PathBuf);
+
+    impl Container {
+        fn new() -> Self {
+            static COUNT: AtomicUsize = AtomicUsize::new(0);
+            let path = std::env::temp_dir().join(format!(
+                "lfs-test-{}-{}.bin",
+                std::process::id(),
+                COUNT.fetch_add(1, Ordering::Relaxed)
+            ));
+            File::create(&path).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for Container {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    fn user(name: &str) -> User {
+        User::new(name, true, true, false)
+    }
+
+    fn pages(fs: &FileSystem, name: &str) -> u64 {
+        fs.stat(name).unwrap().pages
+    }
+
+    #[test]
+    fn denied_rm_all_removes_nothing() {
+        let container = Container::new();
+        let mut fs = FileSystem::new(User::default());
+        fs.create_file("/d", FileType::Directory, "root", "root", 0o777)
+            .unwrap();
+        fs.create_file("/d/x", FileType::Directory, "root", "root", 0o777)
+            .unwrap();
+        fs.create_file("/d/x/a", FileType::File, "bob", "bob", 0o644)
+            .unwrap();
+        fs.write_file("/d/x/a", &[1; 3000], &container.0).unwrap();
+        fs.create_file("/d/x/locked", FileType::Directory, "root", "root", 0o755)
+            .unwrap();
+        fs.create_file("/d/x/locked/f", FileType::File, "root", "root", 0o644)
+            .unwrap();
+        let free_list = fs.free_list.clone();
+
+        fs.set_user(user("bob"));
+        let error = fs.rm_all("/d/x").unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+        assert_eq!(fs.read_file("/d/x/a", &container.0).unwrap(), [1; 3000]);
+        assert!(fs.is_file_exists("/d/x/locked/f"));
+        assert_eq!(fs.free_list, free_list);
+    }
+
+    #[test]
+    fn sticky_tmp_protects_other_users_files() {
+        let container = Container::new();
+        let mut fs = FileSystem::new(User::default());
+        fs.set_user(user("alice"));
+        fs.touch("/tmp/a").unwrap();
+        fs.write_file("/tmp/a", b"alice", &container.0).unwrap();
+
+        fs.set_user(user("bob"));
+        assert!(fs.rm("/tmp/a").is_err());
+        assert!(fs.mv("/tmp/a", "/tmp/b").is_err());
+        assert_eq!(fs.read_file("/tmp/a", &container.0).unwrap(), b"alice");
+        assert!(!fs.is_file_exists("/tmp/b"));
+
+        fs.touch("/tmp/mine").unwrap();
+        fs.mv("/tmp/mine", "/tmp/moved").unwrap();
+        fs.rm("/tmp/moved").unwrap();
+    }
+
+    #[test]
+    fn holes_read_as_zeros_and_take_no_pages() {
+        let container = Container::new();
+        let mut fs = FileSystem::new(User::default());
+        fs.touch("/f").unwrap();
+        fs.truncate("/f", 3 * PAGE_SIZE, &container.0).unwrap();
+        assert_eq!(pages(&fs, "/f"), 0);
+        assert_eq!(
+            fs.read_file("/f", &container.0).unwrap(),
+            vec![0; 3 * PAGE_SIZE as usize]
+        );
+
+        fs.write_at("/f", 5000, b"abc", &container.0).unwrap();
+        assert_eq!(pages(&fs, "/f"), 1);
+        let data = fs.read_file("/f", &container.0).unwrap();
+        assert_eq!(data.len(), 5003);
+        assert!(data[..5000].iter().all(|&byte| byte == 0));
+        assert_eq!(&data[5000..], b"abc");
+    }
+
+    #[test]
+    fn shrinking_frees_pages() {
+        let container = Container::new();
+        let mut fs = FileSystem::new(User::default());
+        fs.touch("/f").unwrap();
+        fs.write_file("/f", &[7; 3 * PAGE_SIZE as usize], &container.0)
+            .unwrap();
+        assert_eq!(pages(&fs, "/f"), 3);
+
+        fs.truncate("/f", 10, &container.0).unwrap();
+        assert_eq!(pages(&fs, "/f"), 1);
+        assert_eq!(fs.free_list.len(), 2);
+        fs.truncate("/f", 0, &container.0).unwrap();
+        assert_eq!(fs.free_list.len(), 3);
+
+        // Growing again doesn't bring back the old contents
+        fs.truncate("/f", 20, &container.0).unwrap();
+        assert_eq!(fs.read_file("/f", &container.0).unwrap(), [0; 20]);
+    }
+
+    #[test]
+    fn only_owners_set_explicit_times() {
+        let mut fs = FileSystem::new(User::default());
+        fs.create_file("/f", FileType::File, "alice", "alice", 0o666)
+            .unwrap();
+        let past = Timestamp::from_civil(2000, 1, 1, 0, 0, 0).unwrap();
+
+        fs.set_user(user("bob"));
+        let error = fs.set_times("/f", Some(past), true, true).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::PermissionDenied);
+        fs.set_times("/f", None, true, true).unwrap();
+        assert_ne!(fs.stat("/f").unwrap().mtime, past);
+
+        fs.set_user(user("alice"));
+        fs.set_times("/f", Some(past), false, true).unwrap();
+        let stat = fs.stat("/f").unwrap();
+        assert_eq!(stat.mtime, past);
+        assert_ne!(stat.atime, past);
+    }
+
+    #[test]
+    fn pages_are_freed_with_the_last_link() {
+        let container = Container::new();
+        let mut fs = FileSystem::new(User::default());
+        fs.touch("/f").unwrap();
+        fs.write_file("/f", &[7; 2 * PAGE_SIZE as usize], &container.0)
+            .unwrap();
+        fs.link("/f", "/g").unwrap();
+        assert_eq!(fs.stat("/g").unwrap().links, 2);
+
+        fs.rm("/f").unwrap();
+        assert!(fs.free_list.is_empty());
+        assert_eq!(
+            fs.read_file("/g", &container.0).unwrap(),
+            [7; 2 * PAGE_SIZE as usize]
+        );
+        fs.rm("/g").unwrap();
+        assert_eq!(fs.free_list.len(), 2);
+    }
+
+    #[test]
+    fn mv_keeps_the_subtree_of_a_directory() {
+        let container = Container::new();
+        let mut fs = FileSystem::new(User::default());
+        fs.mkdir_all("/a/b").unwrap();
+        fs.touch("/a/b/f").unwrap();
+        fs.write_file("/a/b/f", b"hi", &container.0).unwrap();
+
+        fs.mv("/a", "/c").unwrap();
+        assert!(!fs.is_file_exists("/a"));
+        assert_eq!(fs.read_file("/c/b/f", &container.0).unwrap(), b"hi");
+        fs.cd("/c/b").unwrap();
+        assert_eq!(fs.current_path(), "/c/b");
+
+        // Not into itself, though
+        assert!(fs.mv("/c", "/c/b/c").is_err());
+        assert!(fs.is_file_exists("/c/b/f"));
+    }
+
+    #[test]
+    fn cp_refuses_to_copy_a_directory_into_itself() {
+        let container = Container::new();
+        let mut fs = FileSystem::new(User::default());
+        fs.mkdir_all("/a/b").unwrap();
+        fs.touch("/a/f").unwrap();
+
+        assert!(fs.cp("/a", "/a/b", true, false, &container.0).is_err());
+        assert!(fs.cp("/a", "/a", true, false, &container.0).is_err());
+        assert!(!fs.is_file_exists("/a/b/a"));
+
+        fs.cp("/a", "/copy", true, false, &container.0).unwrap();
+        assert!(fs.is_file_exists("/copy/b"));
+        assert!(fs.is_file_exists("/copy/f"));
+    }
+
+    #[test]
+    fn normalize_path_keeps_canonical_paths() {
+        assert_eq!(normalize_path("/"), "/");
+        assert_eq!(normalize_path("/home/bob"), "/home/bob");
+    }
+
+    #[test]
+    fn normalize_path_drops_repeated_and_trailing_slashes() {
+        assert_eq!(normalize_path("//home///bob/"), "/home/bob");
+        assert_eq!(normalize_path("///"), "/");
+    }
+
+    #[test]
+    fn normalize_path_drops_dots() {
+        assert_eq!(normalize_path("/./home/./bob/."), "/home/bob");
+    }
+
+    #[test]
+    fn normalize_path_resolves_dot_dot() {
+        assert_eq!(normalize_path("/home/bob/../alice"), "/home/alice");
+        assert_eq!(normalize_path("/home/bob/.."), "/home");
+        assert_eq!(normalize_path("/a/b/../../c/./d/.."), "/c");
+    }
+
+    #[test]
+    fn normalize_path_stays_at_root_above_it() {
+        assert_eq!(normalize_path("/.."), "/");
+        assert_eq!(normalize_path("/../../tmp"), "/tmp");
+        assert_eq!(normalize_path("/tmp/../../.."), "/");
+    }
+
+    #[test]
+    fn normalize_path_keeps_names_that_only_start_with_dots() {
+        assert_eq!(normalize_path("/.hidden/...x/..y"), "/.hidden/...x/..y");
     }
 }
@@ -1,3 +1,4 @@
 pub mod filesystem;
+pub mod mode;
 pub mod stdio;
-
+pub mod time;
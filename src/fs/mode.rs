@@ -0,0 +1,227 @@
+use super::filesystem::{FileType, S_ISGID, S_ISUID, S_ISVTX};
+
+// Bits each class letter of a symbolic mode may touch
+const WHO_USER: u16 = 0o700 | S_ISUID;
+const WHO_GROUP: u16 = 0o070 | S_ISGID;
+const WHO_OTHER: u16 = 0o007 | S_ISVTX;
+const WHO_ALL: u16 = WHO_USER | WHO_GROUP | WHO_OTHER;
+
+/// A mode argument to `chmod`, either octal (`755`) or symbolic
+/// (`u+x,go-w`, `a=r`, `g=u`).
+#[derive(Debug, Clone)]
+pub enum ModeChange {
+    Absolute(u16),
+    Symbolic(Vec<Clause>),
+}
+
+/// One `[ugoa]*[-+=]...` part of a symbolic mode.
+#[derive(Debug, Clone)]
+pub struct Clause {
+    who: u16,
+    actions: Vec<(char, Perms)>,
+}
+
+#[derive(Debug, Clone)]
+enum Perms {
+    /// Any of `rwxXst`
+    Bits(String),
+    /// Copy the bits of another class, as in `g=u`
+    Copy(char),
+}
+
+impl ModeChange {
+    pub fn parse(spec: &str) -> std::io::Result<Self> {
+        let invalid = || {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid mode: '{}'", spec),
+            )
+        };
+
+        if !spec.is_empty() && spec.chars().all(|c| c.is_digit(8)) {
+            return match u16::from_str_radix(spec, 8) {
+                Ok(mode) if mode <= 0o7777 => Ok(ModeChange::Absolute(mode)),
+                _ => Err(invalid()),
+            };
+        }
+
+        let mut clauses = Vec::new();
+        for part in spec.split(',') {
+            let mut chars = part.chars().peekable();
+
+            let mut who = 0;
+            while let Some(c) = chars.next_if(|c| "ugoa".contains(*c)) {
+                who |= match c {
+                    'u' => WHO_USER,
+                    'g' => WHO_GROUP,
+                    'o' => WHO_OTHER,
+                    _ => WHO_ALL,
+                };
+            }
+            if who == 0 {
+                who = WHO_ALL;
+            }
+
+            let mut actions = Vec::new();
+            while let Some(op) = chars.next_if(|c| "+-=".contains(*c)) {
+                let perms = match chars.peek() {
+                    Some(&c @ ('u' | 'g' | 'o')) => {
+                        chars.next();
+                        Perms::Copy(c)
+                    }
+                    _ => {
+                        let mut bits = String::new();
+                        while let Some(c) = chars.next_if(|c| "rwxXst".contains(*c)) {
+                            bits.push(c);
+                        }
+                        Perms::Bits(bits)
+                    }
+                };
+                actions.push((op, perms));
+            }
+
+            if actions.is_empty() || chars.next().is_some() {
+                return Err(invalid());
+            }
+            clauses.push(Clause { who, actions });
+        }
+        Ok(ModeChange::Symbolic(clauses))
+    }
+
+    /// Returns `mode` with the change applied. `is_dir` decides what `X`
+    /// means.
+    pub fn apply(&self, mode: u16, is_dir: bool) -> u16 {
+        let clauses = match self {
+            ModeChange::Absolute(mode) => return *mode,
+            ModeChange::Symbolic(clauses) => clauses,
+        };
+
+        let mut mode = mode;
+        for clause in clauses {
+            for (op, perms) in &clause.actions {
+                let bits = match perms {
+                    Perms::Bits(letters) => letters.chars().fold(0, |bits, c| {
+                        bits | match c {
+                            'r' => 0o444,
+                            'w' => 0o222,
+                            'x' => 0o111,
+                            'X' if is_dir || mode & 0o111 != 0 => 0o111,
+                            's' => S_ISUID | S_ISGID,
+                            't' => S_ISVTX,
+                            _ => 0,
+                        }
+                    }),
+                    Perms::Copy(class) => {
+                        let shift = match class {
+                            'u' => 6,
+                            'g' => 3,
+                            _ => 0,
+                        };
+                        ((mode >> shift) & 0o7) * 0o111
+                    }
+                } & clause.who;
+
+                mode = match op {
+                    '+' => mode | bits,
+                    '-' => mode & !bits,
+                    _ => (mode & !clause.who) | bits,
+                };
+            }
+        }
+        mode
+    }
+}
+
+/// Renders a mode like `ls -l`, e.g. `drwxr-xr-x` or `-rwsr-x--T`.
+pub fn format_mode(file_type: FileType, mode: u16) -> String {
+    let mut text = String::with_capacity(10);
+    text.push(match file_type {
+        FileType::File => '-',
+        FileType::Directory => 'd',
+        FileType::Symlink => 'l',
+    });
+    for (shift, special, set, unset) in [
+        (6, S_ISUID, 's', 'S'),
+        (3, S_ISGID, 's', 'S'),
+        (0, S_ISVTX, 't', 'T'),
+    ] {
+        let bits = (mode >> shift) & 0o7;
+        text.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        text.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        text.push(match (mode & special != 0, bits & 0o1 != 0) {
+            (true, true) => set,
+            (true, false) => unset,
+            (false, true) => 'x',
+            (false, false) => '-',
+        });
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(spec: &str, mode: u16, is_dir: bool) -> u16 {
+        ModeChange::parse(spec).unwrap().apply(mode, is_dir)
+    }
+
+    #[test]
+    fn octal_modes_replace_everything() {
+        assert_eq!(apply("755", 0o644, false), 0o755);
+        assert_eq!(apply("0", 0o777, false), 0);
+        assert_eq!(apply("4750", 0o644, false), 0o4750);
+    }
+
+    #[test]
+    fn invalid_modes_are_rejected() {
+        for spec in ["", "8", "17777", "u", "u+z", "x+r", "u+r,", "+r g-w"] {
+            assert!(ModeChange::parse(spec).is_err(), "{:?}", spec);
+        }
+    }
+
+    #[test]
+    fn symbolic_add_remove_and_set() {
+        assert_eq!(apply("u+x", 0o644, false), 0o744);
+        assert_eq!(apply("go-r", 0o644, false), 0o600);
+        assert_eq!(apply("a=r", 0o755, false), 0o444);
+        assert_eq!(apply("o=", 0o777, false), 0o770);
+    }
+
+    #[test]
+    fn missing_class_means_all() {
+        assert_eq!(apply("+x", 0o644, false), 0o755);
+        assert_eq!(apply("-w", 0o666, false), 0o444);
+    }
+
+    #[test]
+    fn clauses_apply_in_order() {
+        assert_eq!(apply("u+x,go-w", 0o666, false), 0o744);
+        assert_eq!(apply("a-x,u+x", 0o777, false), 0o766);
+        assert_eq!(apply("u+x-w", 0o644, false), 0o544);
+    }
+
+    #[test]
+    fn copying_another_class() {
+        assert_eq!(apply("g=u", 0o740, false), 0o770);
+        assert_eq!(apply("o+g", 0o750, false), 0o755);
+        assert_eq!(apply("go=u", 0o600, false), 0o666);
+    }
+
+    #[test]
+    fn capital_x_only_applies_to_directories_and_executables() {
+        assert_eq!(apply("a+X", 0o644, false), 0o644);
+        assert_eq!(apply("a+X", 0o644, true), 0o755);
+        assert_eq!(apply("a+X", 0o744, false), 0o755);
+    }
+
+    #[test]
+    fn special_bits_follow_the_class() {
+        assert_eq!(apply("u+s", 0o755, false), 0o4755);
+        assert_eq!(apply("g+s", 0o755, true), 0o2755);
+        assert_eq!(apply("+t", 0o777, true), 0o1777);
+        assert_eq!(apply("u+t", 0o777, true), 0o777);
+        assert_eq!(apply("a-s", 0o6755, false), 0o755);
+        assert_eq!(apply("u=rwx", 0o4644, false), 0o744);
+    }
+}
@@ -2,45 +2,58 @@ use std::path::Path;
 
 use serde::{Deserialize, Serialize};
 
-use super::filesystem::FileSystem;
+use super::filesystem::{FileSystem, O_RDONLY, PAGE_SIZE};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Stdio {
+    // `None` means nothing is connected to stdin, as opposed to an empty pipe
+    #[serde(skip)]
+    input_buffer: Option<Vec<u8>>,
     output_buffer: Vec<u8>,
     error_buffer: Vec<u8>,
 }
 
 impl Stdio {
     pub fn new() -> Self {
-        Stdio { output_buffer: Vec::new(), error_buffer: Vec::new() }
-    
+        Stdio {
+            input_buffer: None,
+            output_buffer: Vec::new(),
+            error_buffer: Vec::new(),
+        }
     }
 
+    /// Streams a file into the output buffer one page at a time.
     pub fn read_file<P: AsRef<Path>>(
         &mut self,
         name: &str,
         path: P,
-        fs: &FileSystem,
+        fs: &mut FileSystem,
     ) -> std::io::Result<()> {
-        let data = fs.read_file(name, path)?;
-        self.output_buffer = data;
-        Ok(())
+        let fd = fs.open(name, O_RDONLY, path)?;
+        let result = loop {
+            match fs.read(fd, PAGE_SIZE) {
+                Ok(data) if data.is_empty() => break Ok(()),
+                Ok(data) => self.output_buffer.extend_from_slice(&data),
+                Err(e) => break Err(e),
+            }
+        };
+        fs.close(fd)?;
+        result
     }
 
-    // pub fn write_file<P: AsRef<Path>>(
-    //     &mut self,
-    //     name: &str,
-    //     path: P,
-    //     fs: &mut FileSystem,
-    // ) -> std::io::Result<()> {
-    //     fs.write_file(name, &self.input_buffer, path)
-    // }
+    /// Takes everything connected to stdin, or `None` if nothing is.
+    pub fn read(&mut self) -> Option<Vec<u8>> {
+        self.input_buffer.take()
+    }
 
-    // pub fn read(&mut self) -> Vec<u8> {
-    //     let data = self.input_buffer.clone();
-    //     self.input_buffer.clear();
-    //     data
-    // }
+    /// Takes the pending output, e.g. to feed it to the next pipeline stage.
+    pub fn take_output(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.output_buffer)
+    }
+
+    pub fn take_error(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.error_buffer)
+    }
 
     pub fn write(&mut self, data: &[u8]) {
         self.output_buffer.extend_from_slice(data);
@@ -49,32 +62,40 @@ impl Stdio {
     pub fn error(&mut self, data: &[u8]) {
         self.error_buffer.extend_from_slice(data);
     }
-    
+
     pub fn print(&mut self) {
+        if self.output_buffer.is_empty() {
+            return;
+        }
         // remove the \n at the start and end of the output_buffer
         if self.output_buffer.ends_with(&[10]) {
-            self.output_buffer.pop(); 
+            self.output_buffer.pop();
         }
         if self.output_buffer.starts_with(&[10]) {
-            self.output_buffer.remove(0); 
+            self.output_buffer.remove(0);
         }
         println!("{}", String::from_utf8_lossy(&self.output_buffer));
         self.output_buffer.clear();
     }
 
     pub fn print_error(&mut self) {
+        if self.error_buffer.is_empty() {
+            return;
+        }
         // remove the \n at the start and end of the error_buffer
         if self.error_buffer.ends_with(&[10]) {
-            self.error_buffer.pop(); 
+            self.error_buffer.pop();
         }
         if self.error_buffer.starts_with(&[10]) {
-            self.error_buffer.remove(0); 
+            self.error_buffer.remove(0);
         }
         eprintln!("{}", String::from_utf8_lossy(&self.error_buffer));
         self.error_buffer.clear();
     }
 
-    // pub fn input(&mut self, data: &[u8]) {
-    //   self.input_buffer.extend_from_slice(data);
-    // }
+    pub fn input(&mut self, data: &[u8]) {
+        self.input_buffer
+            .get_or_insert_with(Vec::new)
+            .extend_from_slice(data);
+    }
 }
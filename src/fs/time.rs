@@ -0,0 +1,440 @@
+use std::{
+    fmt,
+    ops::RangeInclusive,
+    str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Years a timestamp may fall in, the ones with four digits
+const YEARS: RangeInclusive<i64> = 0..=9999;
+
+/// A point in time as seconds and nanoseconds since the Unix epoch. All
+/// times are UTC.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Timestamp {
+    pub secs: i64,
+    pub nanos: u32,
+}
+
+impl Timestamp {
+    pub fn now() -> Self {
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(elapsed) => Self {
+                secs: elapsed.as_secs() as i64,
+                nanos: elapsed.subsec_nanos(),
+            },
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// The given calendar date and time, or `None` if it doesn't exist or
+    /// the year has more than four digits.
+    pub fn from_civil(
+        year: i64,
+        month: u32,
+        day: u32,
+        hour: u32,
+        minute: u32,
+        second: u32,
+    ) -> Option<Self> {
+        if !YEARS.contains(&year)
+            || !(1..=12).contains(&month)
+            || day == 0
+            || day > days_in_month(year, month)
+            || hour > 23
+            || minute > 59
+            || second > 60
+        {
+            return None;
+        }
+        let secs = days_from_civil(year, month, day) * SECONDS_PER_DAY
+            + (hour * 3600 + minute * 60 + second) as i64;
+        Some(Self { secs, nanos: 0 })
+    }
+
+    /// Year, month, day, hour, minute and second.
+    pub fn civil(self) -> (i64, u32, u32, u32, u32, u32) {
+        let days = self.secs.div_euclid(SECONDS_PER_DAY);
+        let seconds = self.secs.rem_euclid(SECONDS_PER_DAY) as u32;
+        let (year, month, day) = civil_from_days(days);
+        (
+            year,
+            month,
+            day,
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60,
+        )
+    }
+
+    /// Parses the argument of `touch -d`: `now`, `@<seconds>`, `YYYY-MM-DD`
+    /// or `YYYY-MM-DD HH:MM[:SS]`, with a space or `T` before the time.
+    pub fn parse_date(date: &str) -> std::io::Result<Self> {
+        let invalid = || {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid date: '{}'", date),
+            )
+        };
+
+        if date == "now" {
+            return Ok(Self::now());
+        }
+        if let Some(secs) = date.strip_prefix('@') {
+            let secs = secs.parse().map_err(|_| invalid())?;
+            let time = Self { secs, nanos: 0 };
+            if !YEARS.contains(&time.civil().0) {
+                return Err(invalid());
+            }
+            return Ok(time);
+        }
+
+        let (day, time) = match date.split_once([' ', 'T']) {
+            Some((day, time)) => (day, Some(time)),
+            None => (date, None),
+        };
+        let numbers = |text: &str, separator: char| -> Option<Vec<u32>> {
+            text.split(separator).map(|n| n.parse().ok()).collect()
+        };
+
+        let [year, month, day] = numbers(day, '-').ok_or_else(invalid)?[..] else {
+            return Err(invalid());
+        };
+        let (hour, minute, second) = match time.map(|time| numbers(time, ':')) {
+            None => (0, 0, 0),
+            Some(Some(time)) => match time[..] {
+                [hour, minute] => (hour, minute, 0),
+                [hour, minute, second] => (hour, minute, second),
+                _ => return Err(invalid()),
+            },
+            Some(None) => return Err(invalid()),
+        };
+        Self::from_civil(year as i64, month, day, hour, minute, second).ok_or_else(invalid)
+    }
+
+    /// Parses the argument of `touch -t`, `[[CC]YY]MMDDhhmm[.ss]`. Without a
+    /// year the current one is used, and a two-digit year means 1969–2068.
+    pub fn parse_stamp(stamp: &str) -> std::io::Result<Self> {
+        let invalid = || {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid date format: '{}'", stamp),
+            )
+        };
+
+        let (digits, second) = match stamp.split_once('.') {
+            Some((digits, second)) if second.len() == 2 => {
+                (digits, second.parse().map_err(|_| invalid())?)
+            }
+            Some(_) => return Err(invalid()),
+            None => (stamp, 0),
+        };
+        if !digits.chars().all(|c| c.is_ascii_digit()) {
+            return Err(invalid());
+        }
+
+        let field = |start: usize| digits[start..start + 2].parse::<u32>().unwrap_or(0);
+        let (year, rest) = match digits.len() {
+            8 => (Self::now().civil().0, 0),
+            10 => {
+                let year = field(0) as i64;
+                (if year < 69 { 2000 + year } else { 1900 + year }, 2)
+            }
+            12 => (digits[..4].parse().map_err(|_| invalid())?, 4),
+            _ => return Err(invalid()),
+        };
+        Self::from_civil(
+            year,
+            field(rest),
+            field(rest + 2),
+            field(rest + 4),
+            field(rest + 6),
+            second,
+        )
+        .ok_or_else(invalid)
+    }
+
+    /// Formats like `ls -l`: `Jun 30 14:05`, or `Jun 30  2023` for times more
+    /// than half a year before or after `now`.
+    pub fn format_short(self, now: Timestamp) -> String {
+        const MONTHS: [&str; 12] = [
+            "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+        ];
+        const HALF_YEAR: u64 = 365 * SECONDS_PER_DAY as u64 / 2;
+
+        let (year, month, day, hour, minute, _) = self.civil();
+        let month = MONTHS[month as usize - 1];
+        if now.secs.abs_diff(self.secs) > HALF_YEAR {
+            format!("{} {:>2}  {}", month, day, year)
+        } else {
+            format!("{} {:>2} {:02}:{:02}", month, day, hour, minute)
+        }
+    }
+}
+
+impl fmt::Display for Timestamp {
+    /// Formats like `stat`, e.g. `2024-06-30 14:05:09.123456789 +0000`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day, hour, minute, second) = self.civil();
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:09} +0000",
+            year, month, day, hour, minute, second, self.nanos
+        )
+    }
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    // Count from March so that the leap day comes last
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month as i64 + 9) % 12) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The inverse of `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// When reading a file updates its access time, like the `strictatime`,
+/// `relatime` and `noatime` mount options.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AtimePolicy {
+    /// On every read
+    #[serde(alias = "strictatime")]
+    Strict,
+    /// Only if the access time is older than the last change or a day old
+    #[default]
+    Relatime,
+    /// Never
+    Noatime,
+}
+
+impl AtimePolicy {
+    /// Whether a read at `now` should move `atime`.
+    pub fn should_update(
+        &self,
+        atime: Timestamp,
+        mtime: Timestamp,
+        ctime: Timestamp,
+        now: Timestamp,
+    ) -> bool {
+        match self {
+            AtimePolicy::Strict => true,
+            AtimePolicy::Relatime => {
+                atime <= mtime || atime <= ctime || now.secs - atime.secs >= SECONDS_PER_DAY
+            }
+            AtimePolicy::Noatime => false,
+        }
+    }
+}
+
+impl FromStr for AtimePolicy {
+    type Err = std::io::Error;
+
+    fn from_str(option: &str) -> Result<Self, Self::Err> {
+        match option {
+            "strict" | "strictatime" => Ok(AtimePolicy::Strict),
+            "relatime" => Ok(AtimePolicy::Relatime),
+            "noatime" => Ok(AtimePolicy::Noatime),
+            _ => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Unknown mount option: '{}'", option),
+            )),
+        }
+    }
+}
+
+impl fmt::Display for AtimePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AtimePolicy::Strict => "strictatime",
+            AtimePolicy::Relatime => "relatime",
+            AtimePolicy::Noatime => "noatime",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(date: &str) -> Timestamp {
+        Timestamp::parse_date(date).unwrap()
+    }
+
+    #[test]
+    fn civil_round_trips() {
+        for (year, month, day) in [
+            (1970, 1, 1),
+            (1969, 12, 31),
+            (2000, 2, 29),
+            (2024, 12, 31),
+            (0, 1, 1),
+            (9999, 12, 31),
+        ] {
+            let time = Timestamp::from_civil(year, month, day, 13, 14, 15).unwrap();
+            assert_eq!(time.civil(), (year, month, day, 13, 14, 15));
+        }
+        assert_eq!(Timestamp::from_civil(1970, 1, 1, 0, 0, 0).unwrap().secs, 0);
+        assert_eq!(
+            Timestamp::from_civil(1969, 12, 31, 23, 59, 59)
+                .unwrap()
+                .secs,
+            -1
+        );
+    }
+
+    #[test]
+    fn leap_years() {
+        assert!(Timestamp::from_civil(2024, 2, 29, 0, 0, 0).is_some());
+        assert!(Timestamp::from_civil(2000, 2, 29, 0, 0, 0).is_some());
+        assert!(Timestamp::from_civil(1900, 2, 29, 0, 0, 0).is_none());
+        assert!(Timestamp::from_civil(2023, 2, 29, 0, 0, 0).is_none());
+        assert_eq!(
+            date("2000-03-01").secs - date("2000-02-28").secs,
+            2 * SECONDS_PER_DAY
+        );
+    }
+
+    #[test]
+    fn impossible_dates() {
+        assert!(Timestamp::from_civil(2024, 13, 1, 0, 0, 0).is_none());
+        assert!(Timestamp::from_civil(2024, 4, 31, 0, 0, 0).is_none());
+        assert!(Timestamp::from_civil(2024, 1, 0, 0, 0, 0).is_none());
+        assert!(Timestamp::from_civil(2024, 1, 1, 24, 0, 0).is_none());
+        assert!(Timestamp::from_civil(10000, 1, 1, 0, 0, 0).is_none());
+        assert!(Timestamp::from_civil(-1, 12, 31, 0, 0, 0).is_none());
+    }
+
+    #[test]
+    fn parse_date_forms() {
+        assert_eq!(date("@0").secs, 0);
+        assert_eq!(date("@-86400").civil(), (1969, 12, 31, 0, 0, 0));
+        assert_eq!(date("2024-06-30").civil(), (2024, 6, 30, 0, 0, 0));
+        assert_eq!(date("2024-06-30 14:05").civil(), (2024, 6, 30, 14, 5, 0));
+        assert_eq!(date("2024-06-30T14:05:09").civil(), (2024, 6, 30, 14, 5, 9));
+        for date in [
+            "",
+            "@",
+            "@1.5",
+            "2024-06",
+            "2024-06-31",
+            "2024-06-30 14",
+            "2024-06-30 14:05:09:01",
+            "yesterday",
+        ] {
+            assert!(Timestamp::parse_date(date).is_err(), "{:?}", date);
+        }
+    }
+
+    #[test]
+    fn parse_date_rejects_far_away_seconds() {
+        let last = Timestamp::from_civil(9999, 12, 31, 23, 59, 59).unwrap();
+        assert_eq!(date(&format!("@{}", last.secs)), last);
+        for secs in [last.secs + 1, i64::MAX, i64::MIN] {
+            assert!(Timestamp::parse_date(&format!("@{}", secs)).is_err());
+        }
+        assert!(Timestamp::parse_date("@-62167219201").is_err());
+        assert_eq!(date("@-62167219200").civil(), (0, 1, 1, 0, 0, 0));
+    }
+
+    #[test]
+    fn parse_stamp_forms() {
+        let stamp = |stamp: &str| Timestamp::parse_stamp(stamp).unwrap().civil();
+        assert_eq!(stamp("202406301405"), (2024, 6, 30, 14, 5, 0));
+        assert_eq!(stamp("202406301405.09"), (2024, 6, 30, 14, 5, 9));
+        assert_eq!(stamp("6806301405").0, 2068);
+        assert_eq!(stamp("6906301405").0, 1969);
+        assert_eq!(stamp("06301405").0, Timestamp::now().civil().0);
+        for stamp in [
+            "",
+            "0630140",
+            "063014051",
+            "2024063014",
+            "06301405.9",
+            "0630x405",
+            "13301405",
+        ] {
+            assert!(Timestamp::parse_stamp(stamp).is_err(), "{:?}", stamp);
+        }
+    }
+
+    #[test]
+    fn format_short_shows_the_year_when_far_away() {
+        let now = date("2024-06-30 14:05");
+        assert_eq!(date("2024-03-01 09:30").format_short(now), "Mar  1 09:30");
+        assert_eq!(date("2023-06-30 14:05").format_short(now), "Jun 30  2023");
+        assert_eq!(date("2025-06-30").format_short(now), "Jun 30  2025");
+        let far = Timestamp {
+            secs: i64::MIN,
+            nanos: 0,
+        };
+        let now = Timestamp {
+            secs: i64::MAX,
+            nanos: 0,
+        };
+        assert!(far.format_short(now).ends_with(&far.civil().0.to_string()));
+    }
+
+    #[test]
+    fn atime_policy_names() {
+        for name in ["strict", "strictatime", "relatime", "noatime"] {
+            let parsed: AtimePolicy = name.parse().unwrap();
+            let json: AtimePolicy = serde_json::from_str(&format!("\"{}\"", name)).unwrap();
+            assert_eq!(parsed, json);
+            // What `mount` prints is accepted back
+            assert_eq!(parsed.to_string().parse::<AtimePolicy>().unwrap(), parsed);
+        }
+        assert!("atime".parse::<AtimePolicy>().is_err());
+    }
+
+    #[test]
+    fn display_like_stat() {
+        let time = Timestamp {
+            nanos: 123456789,
+            ..date("2024-06-30 14:05:09")
+        };
+        assert_eq!(time.to_string(), "2024-06-30 14:05:09.123456789 +0000");
+        assert_eq!(
+            date("@0").to_string(),
+            "1970-01-01 00:00:00.000000000 +0000"
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use clap::{Arg, ArgAction, Command};
 use sh::shell::Shell;
 
+mod config;
 mod fs;
 mod sh;
 mod user;
@@ -18,14 +19,23 @@ fn main() {
                 .num_args(1)
                 .help("Specify the user"),
         )
+        .arg(
+            Arg::new("password-stdin")
+                .long("password-stdin")
+                .action(ArgAction::SetTrue)
+                .help("Read the password from the first line of stdin"),
+        )
         .get_matches();
 
     let user = matches
         .get_one::<String>("user")
         .unwrap_or(&"root".to_string())
         .to_string();
-    
-    if let Err(e) = Shell::init(user) {
+
+    let password_stdin = matches.get_flag("password-stdin");
+
+    if let Err(e) = Shell::init(user, password_stdin) {
         eprintln!("{}", e);
-    } 
+        std::process::exit(1);
+    }
 }
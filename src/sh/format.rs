@@ -0,0 +1,244 @@
+use crate::fs::{
+    filesystem::{DirEntry, FileType, Stat, PAGE_SIZE},
+    mode::format_mode,
+    time::Timestamp,
+};
+
+/// Flags of `ls`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LsOptions {
+    /// `-l`: mode, links, owner, group, size and modification time
+    pub long: bool,
+    /// `-a`: include names starting with `.`
+    pub all: bool,
+    /// `-R`: list subdirectories too
+    pub recursive: bool,
+    /// `-h`: sizes like `1.5K` in long listings
+    pub human: bool,
+    pub sort: SortBy,
+    /// `-r`
+    pub reverse: bool,
+    /// `-1`: one name per line
+    pub one_per_line: bool,
+    /// Color names by file type
+    pub color: bool,
+}
+
+/// Order of an `ls` listing.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum SortBy {
+    #[default]
+    Name,
+    /// `-S`: largest first
+    Size,
+    /// `-t`: newest first
+    Time,
+}
+
+/// What `stat` calls each kind of file.
+pub fn type_name(stat: &Stat) -> &'static str {
+    match stat.file_type {
+        FileType::File if stat.size == 0 => "regular empty file",
+        FileType::File => "regular file",
+        FileType::Directory => "directory",
+        FileType::Symlink => "symbolic link",
+    }
+}
+
+/// `name`, or `'name' -> 'target'` for a symbolic link.
+fn quoted_name(stat: &Stat, name: &str) -> String {
+    match &stat.target {
+        Some(target) => format!("'{}' -> '{}'", name, target),
+        None => format!("'{}'", name),
+    }
+}
+
+/// The default output of `stat`.
+pub fn stat(stat: &Stat, name: &str) -> String {
+    format!(
+        "  File: {}\n  Size: {:<15} Pages: {:<10} Blocks: {:<10} IO Block: {:<6} {}\n\
+         Inode: {:<14} Links: {}\n\
+         Access: ({:04o}/{})  Uid: {}  Gid: {}\n\
+         Access: {}\nModify: {}\nChange: {}\n Birth: {}\n",
+        quoted_name(stat, name),
+        stat.size,
+        stat.pages,
+        stat.blocks(),
+        PAGE_SIZE,
+        type_name(stat),
+        stat.inode,
+        stat.links,
+        stat.mode,
+        format_mode(stat.file_type, stat.mode),
+        stat.owner,
+        stat.group,
+        stat.atime,
+        stat.mtime,
+        stat.ctime,
+        stat.btime,
+    )
+}
+
+/// Expands the directives of `stat --format`, such as `%n` for the name and
+/// `%s` for the size. Unknown directives are kept as they are.
+pub fn stat_format(stat: &Stat, name: &str, format: &str) -> String {
+    let mut text = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            text.push(c);
+            continue;
+        }
+        let Some(directive) = chars.next() else {
+            text.push('%');
+            break;
+        };
+        match directive {
+            '%' => text.push('%'),
+            'n' => text.push_str(name),
+            'N' => text.push_str(&quoted_name(stat, name)),
+            's' => text.push_str(&stat.size.to_string()),
+            'P' => text.push_str(&stat.pages.to_string()),
+            'b' => text.push_str(&stat.blocks().to_string()),
+            'B' => text.push_str("512"),
+            'o' => text.push_str(&PAGE_SIZE.to_string()),
+            'i' => text.push_str(&stat.inode.to_string()),
+            'h' => text.push_str(&stat.links.to_string()),
+            'a' => text.push_str(&format!("{:o}", stat.mode)),
+            'A' => text.push_str(&format_mode(stat.file_type, stat.mode)),
+            'F' => text.push_str(type_name(stat)),
+            'U' => text.push_str(&stat.owner),
+            'G' => text.push_str(&stat.group),
+            'x' => text.push_str(&stat.atime.to_string()),
+            'y' => text.push_str(&stat.mtime.to_string()),
+            'z' => text.push_str(&stat.ctime.to_string()),
+            'w' => text.push_str(&stat.btime.to_string()),
+            'X' => text.push_str(&stat.atime.secs.to_string()),
+            'Y' => text.push_str(&stat.mtime.secs.to_string()),
+            'Z' => text.push_str(&stat.ctime.secs.to_string()),
+            'W' => text.push_str(&stat.btime.secs.to_string()),
+            _ => {
+                text.push('%');
+                text.push(directive);
+            }
+        }
+    }
+    text
+}
+
+/// Puts `entries` in the order `ls` shows them, by name unless `-S` or `-t`
+/// was given.
+pub fn sort_entries(entries: &mut [DirEntry], options: &LsOptions) {
+    entries.sort_by(|a, b| {
+        let order = match options.sort {
+            SortBy::Name => std::cmp::Ordering::Equal,
+            SortBy::Size => b.stat.size.cmp(&a.stat.size),
+            SortBy::Time => b.stat.mtime.cmp(&a.stat.mtime),
+        };
+        order.then_with(|| a.name.cmp(&b.name))
+    });
+    if options.reverse {
+        entries.reverse();
+    }
+}
+
+/// `entries` as `ls` shows them. `total` adds the `total` line of a
+/// directory's long listing.
+pub fn listing(entries: &[DirEntry], options: &LsOptions, total: bool) -> String {
+    let name = |entry: &DirEntry| {
+        if !options.color {
+            return entry.name.clone();
+        }
+        let color = match entry.stat.file_type {
+            FileType::Directory => "\x1b[34m",
+            FileType::File => "\x1b[33m", // yellow
+            FileType::Symlink => "\x1b[36m",
+        };
+        let white = "\x1b[0m";
+        format!("{}{}{}", color, entry.name, white)
+    };
+    let size = |bytes: u64| {
+        if options.human {
+            human_size(bytes)
+        } else {
+            bytes.to_string()
+        }
+    };
+
+    let mut text = String::new();
+    if !options.long {
+        let names: Vec<String> = entries.iter().map(name).collect();
+        if options.one_per_line {
+            for name in names {
+                text.push_str(&name);
+                text.push('\n');
+            }
+        } else if !names.is_empty() {
+            text.push_str(&names.join("  "));
+            text.push('\n');
+        }
+        return text;
+    }
+
+    if total {
+        // Counted in kilobytes, or in human units with -h
+        let bytes = entries.iter().map(|entry| entry.stat.pages).sum::<u64>() * PAGE_SIZE;
+        let total = if options.human {
+            human_size(bytes)
+        } else {
+            (bytes / 1024).to_string()
+        };
+        text.push_str(&format!("total {}\n", total));
+    }
+    let width = |column: &dyn Fn(&Stat) -> String| {
+        entries
+            .iter()
+            .map(|entry| column(&entry.stat).len())
+            .max()
+            .unwrap_or(0)
+    };
+    let links_width = width(&|stat| stat.links.to_string());
+    let owner_width = width(&|stat| stat.owner.clone());
+    let group_width = width(&|stat| stat.group.clone());
+    let size_width = width(&|stat| size(stat.size));
+    let now = Timestamp::now();
+    for entry in entries {
+        let stat = &entry.stat;
+        text.push_str(&format!(
+            "{} {:>links_width$} {:<owner_width$} {:<group_width$} {:>size_width$} {} {}",
+            format_mode(stat.file_type, stat.mode),
+            stat.links,
+            stat.owner,
+            stat.group,
+            size(stat.size),
+            stat.mtime.format_short(now),
+            name(entry),
+        ));
+        if let Some(target) = &stat.target {
+            text.push_str(&format!(" -> {}", target));
+        }
+        text.push('\n');
+    }
+    text
+}
+
+/// A size like `ls -h` shows it: `512`, `1.5K`, `12M`. Values are rounded up.
+fn human_size(bytes: u64) -> String {
+    const UNITS: [char; 6] = ['K', 'M', 'G', 'T', 'P', 'E'];
+    if bytes < 1024 {
+        return bytes.to_string();
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if value < 10.0 {
+        let tenths = (value * 10.0).ceil() / 10.0;
+        if tenths < 10.0 {
+            return format!("{:.1}{}", tenths, UNITS[unit]);
+        }
+    }
+    format!("{}{}", value.ceil() as u64, UNITS[unit])
+}
@@ -0,0 +1,320 @@
+use std::{
+    fmt,
+    io::{Error, ErrorKind},
+};
+
+/// A single lexical unit of a command line.
+///
+/// Quotes and escapes are already resolved inside `Word`, so `"my file"` and
+/// `my\ file` both produce `Word("my file")`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Word(String),
+    /// `|`
+    Pipe,
+    /// `;`
+    Semicolon,
+    /// `&&`
+    And,
+    /// `||`
+    Or,
+    /// `>`
+    RedirectOut,
+    /// `>>`
+    RedirectAppend,
+    /// `<`
+    RedirectIn,
+    /// `2>`
+    RedirectErr,
+    /// `2>>`
+    RedirectErrAppend,
+    /// `2>&1`
+    ErrToOut,
+}
+
+impl Token {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Token::Word(word) => word,
+            Token::Pipe => "|",
+            Token::Semicolon => ";",
+            Token::And => "&&",
+            Token::Or => "||",
+            Token::RedirectOut => ">",
+            Token::RedirectAppend => ">>",
+            Token::RedirectIn => "<",
+            Token::RedirectErr => "2>",
+            Token::RedirectErrAppend => "2>>",
+            Token::ErrToOut => "2>&1",
+        }
+    }
+}
+
+/// An error that is already phrased for the user, such as a usage line, and
+/// is reported without the usual `Error:` prefix.
+#[derive(Debug)]
+pub struct Message(pub String);
+
+impl fmt::Display for Message {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for Message {}
+
+pub fn syntax_error(message: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidInput,
+        Message(format!("Syntax error: {}", message)),
+    )
+}
+
+/// Splits a command line into words and operators.
+///
+/// Single quotes keep everything literally, double quotes only treat `\"`,
+/// `\\`, `\$` and `` \` `` as escapes, and an unquoted backslash escapes the
+/// next character. An unquoted `~` or `~user` at the start of a word becomes
+/// the home directory `home` returns for the user name, which is empty for
+/// the current user.
+pub fn tokenize(input: &str, home: impl Fn(&str) -> Option<String>) -> std::io::Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    // `word` is `Some` as soon as a word has started, so that `""` still
+    // produces an empty argument.
+    let mut word: Option<String> = None;
+
+    while let Some(c) = chars.next() {
+        match c {
+            ' ' | '\t' | '\n' | '\r' => {
+                if let Some(word) = word.take() {
+                    tokens.push(Token::Word(word));
+                }
+            }
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(syntax_error("unterminated single quote")),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(syntax_error("unterminated double quote")),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(syntax_error("unterminated double quote")),
+                    }
+                }
+            }
+            '\\' => {
+                let word = word.get_or_insert_with(String::new);
+                match chars.next() {
+                    Some(c) => word.push(c),
+                    None => word.push('\\'),
+                }
+            }
+            '|' | ';' | '&' | '>' | '<' => {
+                if let Some(word) = word.take() {
+                    tokens.push(Token::Word(word));
+                }
+                let token = match c {
+                    '|' if chars.next_if_eq(&'|').is_some() => Token::Or,
+                    '|' => Token::Pipe,
+                    ';' => Token::Semicolon,
+                    '&' if chars.next_if_eq(&'&').is_some() => Token::And,
+                    '&' => return Err(syntax_error("background jobs are not supported")),
+                    '>' if chars.next_if_eq(&'>').is_some() => Token::RedirectAppend,
+                    '>' => Token::RedirectOut,
+                    _ => Token::RedirectIn,
+                };
+                tokens.push(token);
+            }
+            // A bare `2` directly followed by `>` redirects stderr
+            '2' if word.is_none() && chars.peek() == Some(&'>') => {
+                chars.next();
+                let token = if chars.next_if_eq(&'>').is_some() {
+                    Token::RedirectErrAppend
+                } else if chars.next_if_eq(&'&').is_some() {
+                    if chars.next_if_eq(&'1').is_none() {
+                        return Err(syntax_error("only '2>&1' is supported"));
+                    }
+                    Token::ErrToOut
+                } else {
+                    Token::RedirectErr
+                };
+                tokens.push(token);
+            }
+            '~' if word.is_none() => {
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || "_-.".contains(*c)) {
+                    name.push(c);
+                }
+                // `~nobody` and `~x"y"` stay as they are
+                let ends_name = matches!(
+                    chars.peek(),
+                    None | Some('/' | ' ' | '\t' | '|' | ';' | '&' | '>' | '<')
+                );
+                word = Some(match home(&name).filter(|_| ends_name) {
+                    Some(home) => home,
+                    None => format!("~{}", name),
+                });
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+
+    if let Some(word) = word {
+        tokens.push(Token::Word(word));
+    }
+
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(input: &str) -> Vec<Token> {
+        tokenize(input, |_| None).unwrap()
+    }
+
+    fn word(text: &str) -> Token {
+        Token::Word(text.to_string())
+    }
+
+    #[test]
+    fn splits_on_whitespace() {
+        assert_eq!(
+            words("  ls\t-l  /tmp "),
+            [word("ls"), word("-l"), word("/tmp")]
+        );
+        assert!(words("   ").is_empty());
+    }
+
+    #[test]
+    fn single_quotes_are_literal() {
+        assert_eq!(
+            words(r#"echo 'a "b" \n $x'"#),
+            [word("echo"), word(r#"a "b" \n $x"#)]
+        );
+    }
+
+    #[test]
+    fn double_quotes_only_escape_some_characters() {
+        assert_eq!(words(r#""a\"b\\c\d""#), [word(r#"a"b\c\d"#)]);
+    }
+
+    #[test]
+    fn quotes_join_adjacent_text() {
+        assert_eq!(words(r#"my' 'file"s" x"#), [word("my files"), word("x")]);
+        assert_eq!(words(r"my\ file"), [word("my file")]);
+    }
+
+    #[test]
+    fn empty_quotes_make_an_empty_word() {
+        assert_eq!(words(r#"echo "" ''"#), [word("echo"), word(""), word("")]);
+    }
+
+    #[test]
+    fn trailing_backslash_is_kept() {
+        assert_eq!(words(r"a\"), [word(r"a\")]);
+    }
+
+    #[test]
+    fn unterminated_quotes_are_errors() {
+        assert!(tokenize("echo 'a", |_| None).is_err());
+        assert!(tokenize(r#"echo "a"#, |_| None).is_err());
+        assert!(tokenize(r#"echo "a\"#, |_| None).is_err());
+    }
+
+    #[test]
+    fn operators_need_no_spaces() {
+        assert_eq!(
+            words("a|b||c&&d;e"),
+            [
+                word("a"),
+                Token::Pipe,
+                word("b"),
+                Token::Or,
+                word("c"),
+                Token::And,
+                word("d"),
+                Token::Semicolon,
+                word("e"),
+            ]
+        );
+        assert!(tokenize("sleep &", |_| None).is_err());
+    }
+
+    #[test]
+    fn quoted_operators_are_words() {
+        assert_eq!(
+            words("echo '|' \";\" \\>"),
+            [word("echo"), word("|"), word(";"), word(">")]
+        );
+    }
+
+    #[test]
+    fn redirections() {
+        assert_eq!(
+            words("a >f >>g <h 2>e 2>>e 2>&1"),
+            [
+                word("a"),
+                Token::RedirectOut,
+                word("f"),
+                Token::RedirectAppend,
+                word("g"),
+                Token::RedirectIn,
+                word("h"),
+                Token::RedirectErr,
+                word("e"),
+                Token::RedirectErrAppend,
+                word("e"),
+                Token::ErrToOut,
+            ]
+        );
+        assert!(tokenize("a 2>&2", |_| None).is_err());
+    }
+
+    #[test]
+    fn a_two_inside_a_word_is_not_a_redirection() {
+        assert_eq!(words("a2>f"), [word("a2"), Token::RedirectOut, word("f")]);
+        assert_eq!(words("'2'>f"), [word("2"), Token::RedirectOut, word("f")]);
+    }
+
+    #[test]
+    fn tilde_expands_at_the_start_of_a_word() {
+        let home = |name: &str| match name {
+            "" => Some("/home/me".to_string()),
+            "bob" => Some("/home/bob".to_string()),
+            _ => None,
+        };
+        let tokens = tokenize("cd ~ ~/x ~bob/y ~nobody a~ '~' ~bob\"z\"", home).unwrap();
+        assert_eq!(
+            tokens,
+            [
+                word("cd"),
+                word("/home/me"),
+                word("/home/me/x"),
+                word("/home/bob/y"),
+                word("~nobody"),
+                word("a~"),
+                word("~"),
+                word("~bobz"),
+            ]
+        );
+    }
+}
@@ -1 +1,3 @@
-pub mod shell;
\ No newline at end of file
+pub mod format;
+pub mod lexer;
+pub mod shell;
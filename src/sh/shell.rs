@@ -1,61 +1,126 @@
+use std::fmt;
 use std::fs::File;
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, SeekFrom, Write};
 use std::path::Path;
 
 use crate::{
-    fs::{filesystem::FileSystem, stdio::Stdio},
-    user::{User, Users},
+    config::Config,
+    fs::{
+        filesystem::{
+            DirEntry, FileSystem, FileType, O_APPEND, O_CREAT, O_RDONLY, O_TRUNC, O_WRONLY,
+        },
+        mode::ModeChange,
+        stdio::Stdio,
+        time::Timestamp,
+    },
+    user::{Group, Groups, Permissions, User, Users},
 };
 
+use super::{
+    format::{self, LsOptions, SortBy},
+    lexer::{syntax_error, tokenize, Message, Token},
+};
+
+const CONFIG_PATH: &str = "config.json";
+const USERS_PATH: &str = "users.json";
+const GROUPS_PATH: &str = "groups.json";
+const PASSWORD_ATTEMPTS: u32 = 3;
+const MAX_SCRIPT_DEPTH: usize = 16;
+
 #[derive(Debug, Clone)]
 pub struct Shell {
+    config: Config,
     current_user: User,
+    /// Users to return to on `exit`, pushed by `su` and `sudo`
+    previous_users: Vec<User>,
+    users: Users,
+    groups: Groups,
     stdio: Stdio,
+    running: bool,
+    /// How many scripts are running inside each other
+    script_depth: usize,
+    /// Set by `exit` in a script to skip the rest of that script
+    script_exited: bool,
+    /// Whether the output of the running command goes straight to a
+    /// terminal, which turns on `ls --color=auto`
+    terminal_output: bool,
 }
 
 impl Shell {
-    pub fn init(user: String) -> std::io::Result<()> {
-        let users_path = "users.json";
-
-        let mut users = if Path::new(users_path).exists() {
-            Users::load(users_path)?
+    pub fn init(user: String, password_stdin: bool) -> std::io::Result<()> {
+        let config = if Path::new(CONFIG_PATH).exists() {
+            Config::load(CONFIG_PATH)?
         } else {
-            File::create(users_path)?;
+            Config::default()
+        };
+        let mut users = if Path::new(USERS_PATH).exists() {
+            Users::load(USERS_PATH)?
+        } else {
+            File::create(USERS_PATH)?;
             Users::new()
         };
+        let mut groups = if Path::new(GROUPS_PATH).exists() {
+            Groups::load(GROUPS_PATH)?
+        } else {
+            Groups::new()
+        };
 
-        let user = if users.get_users().is_empty() {
-            if user == "root" {
-                let user = User::new("root", true, true, true);
-                users.add_user(user.clone());
-                user
-            } else {
-                let user = User::new(&user, true, true, false);
-                users.add_user(user.clone());
-                user
+        // root always exists and never goes without a password, so nobody
+        // gets in as root unasked. The first run chooses the password.
+        if users.get_user("root").is_none() {
+            users.add_user(User::default());
+        }
+        let root_password_set = !users.get_user("root").is_some_and(User::has_password);
+        if root_password_set {
+            println!("root has no password yet, please choose one.");
+            let password = choose_password(password_stdin)?;
+            if let Some(root) = users.get_user_mut("root") {
+                root.set_password(&password)?;
             }
-        } else {
-            let mut specified_user: Option<User> = None;
-            for i in users.get_users() {
-                if i.get_user_name() == user {
-                    specified_user = Some(i.clone());
-                    break;
-                } else {
-                    continue;
+            users.save(USERS_PATH)?;
+        }
+
+        let user = match users.get_user(&user) {
+            Some(user) => {
+                // Whoever just chose the root password doesn't type it again
+                if !(root_password_set && user.get_user_name() == "root") {
+                    login(user, password_stdin)?;
                 }
+                user.clone()
             }
-            if let Some(user) = specified_user {
-                user
-            } else {
-                let user = User::new(&user, true, true, false);
+            None if config.create_users => {
+                println!("Creating {}, please choose a password.", user);
+                let mut user = User::new(&user, true, true, false);
+                user.set_password(&choose_password(password_stdin)?)?;
                 users.add_user(user.clone());
                 user
             }
+            None => return Err(login_incorrect()),
         };
 
-        users.save(users_path)?;
-        
-        let mut shell = Self { current_user: user, stdio: Stdio::new()};
+        users.save(USERS_PATH)?;
+
+        // Every user has a group of their own, and the default sudoers
+        // policy refers to `sudo`
+        for name in [user.get_group_name(), "sudo".to_string()] {
+            if groups.get_group(&name).is_none() {
+                groups.add_group(Group::new(&name));
+            }
+        }
+        groups.save(GROUPS_PATH)?;
+
+        let mut shell = Self {
+            config,
+            current_user: user,
+            previous_users: Vec::new(),
+            users,
+            groups,
+            stdio: Stdio::new(),
+            running: true,
+            script_depth: 0,
+            script_exited: false,
+            terminal_output: false,
+        };
 
         shell.run()?;
 
@@ -76,198 +141,1922 @@ impl Shell {
             File::create(fs_path)?;
             FileSystem::new(self.current_user.clone())
         };
+        fs.set_groups(self.groups.groups_of(&self.current_user));
+        fs.set_atime_policy(self.config.atime);
 
-        let mut pointer = ">";
-        if self.current_user.get_user_name() == "root" {
-            pointer = "$";
+        // The home directory is made by root on first login
+        fs.set_user(User::default());
+        let home = self.create_home(&mut fs, &self.current_user);
+        self.switch_fs_user(&mut fs);
+        if let Err(e) = home.and_then(|_| fs.cd(&home_dir(&self.current_user.get_user_name()))) {
+            eprintln!("Cannot enter the home directory ({}), starting in /", e);
+            fs.cd("/")?;
         }
 
         println!("Welcome! {}", self.current_user.get_user_name());
 
-        loop {
+        while self.running {
+            let mut pointer = ">";
+            if self.current_user.get_user_name() == "root" {
+                pointer = "$";
+            }
             print!(
                 "{} {} {} ",
                 self.current_user.get_user_name(),
-                fs.current_path,
+                fs.current_path(),
                 pointer
             );
             io::stdout().flush().unwrap();
 
             let mut input = String::new();
-            io::stdin().read_line(&mut input).unwrap();
-            let input = input.trim();
+            if io::stdin().read_line(&mut input)? == 0 {
+                // EOF behaves like `exit`
+                println!();
+                break;
+            }
 
-            let parts: Vec<&str> = input.split_whitespace().collect();
-            if parts.is_empty() {
-                continue;
+            match self.tokenize_line(input.trim()) {
+                Ok(tokens) => self.execute_line(&mut fs, &tokens, container_path),
+                Err(e) => self.report_error(&e),
+            }
+        }
+
+        fs.save(fs_path)?;
+
+        Ok(())
+    }
+
+    /// Runs a command list, honouring `;`, `&&` and `||` between commands.
+    fn execute_line(&mut self, fs: &mut FileSystem, tokens: &[Token], container_path: &str) {
+        let commands = match split_list(tokens) {
+            Ok(commands) => commands,
+            Err(e) => {
+                self.report_error(&e);
+                return;
             }
+        };
 
-            let command = parts[0];
-            let args = &parts[1..];
-
-            // let mut input_redirect: Option<String> = None;
-            // let mut output_redirect: Option<String> = None;
-
-            // for (i, part) in parts.iter().enumerate() {
-            //     if *part == "<" {
-            //         input_redirect = Some(parts[i + 1].to_string());
-            //         args = &parts[1..i];
-            //     } else if *part == ">" {
-            //         output_redirect = Some(parts[i + 1].to_string());
-            //         args = &parts[1..i];
-            //     }
-            // }
-
-            match command {
-                "cd" => {
-                    if args.len() > 0 {
-                        if let Err(e) = fs.cd(args[0]) {
-                            self.stdio.error(format!("Error: {}", e).as_bytes());
-                            self.stdio.print_error();
-                            continue;
-                        };
-                    } else {
-                        self.stdio.write("Usage: cd <path>".as_bytes());
-                        self.stdio.print();
+        let mut succeeded = true;
+        for (connector, command) in commands {
+            if !self.running || self.script_exited {
+                break;
+            }
+            let should_run = match connector {
+                Token::And => succeeded,
+                Token::Or => !succeeded,
+                _ => true,
+            };
+            if should_run {
+                succeeded = self.execute_pipeline(fs, command, container_path);
+            }
+        }
+    }
+
+    /// Runs `a | b | c`, feeding each stage's output buffer into the next
+    /// stage's input. Only the last stage's output reaches the terminal.
+    fn execute_pipeline(
+        &mut self,
+        fs: &mut FileSystem,
+        tokens: &[Token],
+        container_path: &str,
+    ) -> bool {
+        let stages: Vec<&[Token]> = tokens.split(|token| *token == Token::Pipe).collect();
+        if stages.iter().any(|stage| stage.is_empty()) {
+            self.report_error(&syntax_error("unexpected token '|'"));
+            return false;
+        }
+
+        let mut succeeded = true;
+        for (i, stage) in stages.iter().enumerate() {
+            if i > 0 {
+                let output = self.stdio.take_output();
+                self.stdio.input(&output);
+            }
+            self.terminal_output =
+                i + 1 == stages.len() && self.script_depth == 0 && io::stdout().is_terminal();
+            succeeded = self.execute(fs, stage, container_path);
+            // Whatever the stage didn't consume is discarded, like a closed pipe
+            self.stdio.read();
+        }
+        // Inside a script the output is collected by `run_script`
+        if self.script_depth == 0 {
+            self.stdio.print();
+        }
+
+        succeeded
+    }
+
+    /// Runs a single command with its redirections applied. Errors are
+    /// reported here so that `2>` can capture them.
+    fn execute(&mut self, fs: &mut FileSystem, tokens: &[Token], container_path: &str) -> bool {
+        let command = match parse_command(tokens) {
+            Ok(command) => command,
+            Err(e) => {
+                self.report_error(&e);
+                return false;
+            }
+        };
+
+        if command.output != Target::Inherit {
+            self.terminal_output = false;
+        }
+
+        // Like a real shell, output files are created or truncated before
+        // the command runs
+        if let Err(e) = self.prepare_redirects(fs, &command, container_path) {
+            self.report_error(&e);
+            return false;
+        }
+
+        let mut result = Ok(());
+        if let [name, args @ ..] = command.args.as_slice() {
+            result = self.run_builtin(fs, name, args, container_path);
+        }
+        if let Err(e) = &result {
+            self.write_error(e);
+        }
+
+        if let Err(e) = self.route_output(fs, &command, container_path) {
+            self.write_error(&e);
+            self.stdio.print_error();
+            return false;
+        }
+
+        result.is_ok()
+    }
+
+    fn prepare_redirects(
+        &mut self,
+        fs: &mut FileSystem,
+        command: &Command,
+        container_path: &str,
+    ) -> std::io::Result<()> {
+        if let Some(name) = &command.input {
+            let data = fs.read_file(name, container_path)?;
+            self.stdio.read();
+            self.stdio.input(&data);
+        }
+
+        for target in [&command.output, &command.error] {
+            if let Target::File { name, append } = target {
+                let mode = if *append { O_APPEND } else { O_TRUNC };
+                let fd = fs.open(name, O_WRONLY | O_CREAT | mode, container_path)?;
+                fs.close(fd)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends the buffered output and errors of a command where its
+    /// redirections point: a file, or the inherited pipe/terminal.
+    fn route_output(
+        &mut self,
+        fs: &mut FileSystem,
+        command: &Command,
+        container_path: &str,
+    ) -> std::io::Result<()> {
+        let mut output = self.stdio.take_output();
+        let error = self.stdio.take_error();
+
+        match &command.error {
+            Target::Inherit => {
+                self.stdio.error(&error);
+                self.stdio.print_error();
+            }
+            Target::Stdout => output.extend_from_slice(&error),
+            // `> f 2>&1` sends both streams to the same file
+            target if *target == command.output => output.extend_from_slice(&error),
+            Target::File { name, .. } => append_to_file(fs, name, &error, container_path)?,
+        }
+
+        match &command.output {
+            Target::File { name, .. } => append_to_file(fs, name, &output, container_path),
+            _ => {
+                self.stdio.write(&output);
+                Ok(())
+            }
+        }
+    }
+
+    fn run_builtin(
+        &mut self,
+        fs: &mut FileSystem,
+        command: &str,
+        args: &[String],
+        container_path: &str,
+    ) -> std::io::Result<()> {
+        match command {
+            "cd" => match args {
+                [] => fs.cd(&home_dir(&self.current_user.get_user_name()))?,
+                [path] => fs.cd(path)?,
+                _ => return Err(usage("cd [path]")),
+            },
+            "ls" => self.ls(fs, args)?,
+            "touch" => self.touch(fs, args)?,
+            "stat" => self.stat(fs, args)?,
+            "mount" => match args {
+                [] => {
+                    let options = format!("rw,{}", fs.atime_policy());
+                    self.stdio.write(
+                        format!("{} on / type simfs ({})\n", container_path, options).as_bytes(),
+                    );
+                }
+                [flag, options] | [flag, options, _] if flag == "-o" => {
+                    if args.get(2).is_some_and(|target| target != "/") {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidInput,
+                            "Only / can be remounted",
+                        ));
+                    }
+                    let mut options = options.split(',');
+                    if options.next() != Some("remount") {
+                        return Err(usage("mount [-o remount,<option>... [/]]"));
                     }
+                    self.require_root()?;
+                    for option in options {
+                        fs.set_atime_policy(option.parse()?);
+                    }
+                }
+                _ => return Err(usage("mount [-o remount,<option>... [/]]")),
+            },
+            "cat" => {
+                if args.is_empty() {
+                    let input = self.read_stdin("cat <filename>...")?;
+                    self.stdio.write(&input);
                 }
-                "ls" => {
-                    fs.ls();
+                for name in args {
+                    self.stdio.read_file(name, container_path, fs)?;
                 }
-                "touch" => {
-                    if args.len() > 0 {
-                        if let Err(e) = fs.touch(args[0]) {
-                            self.stdio.error(format!("Error: {}", e).as_bytes());
-                            self.stdio.print_error();
-                            continue;
-                        };
+            }
+            "mkdir" => {
+                let (flags, names) = split_flags(args);
+                if names.is_empty() || flags.iter().any(|&flag| flag != 'p') {
+                    return Err(usage("mkdir [-p] <directory>..."));
+                }
+                for name in &names {
+                    if flags.contains(&'p') {
+                        fs.mkdir_all(name)?;
                     } else {
-                        self.stdio.write("Usage: touch <filename>".as_bytes());
-                        self.stdio.print();
+                        fs.mkdir(name)?;
                     }
                 }
-                "cat" => {
-                    if !self.current_user.permissions.can_read {
-                        self.stdio.write("Permission denied".as_bytes());
-                        self.stdio.print();
-                        continue;
+            }
+            "rm" => self.rm(fs, args)?,
+            "rmdir" => {
+                let (flags, names) = split_flags(args);
+                if names.is_empty() || !flags.is_empty() {
+                    return Err(usage("rmdir <directory>..."));
+                }
+                for name in &names {
+                    fs.rmdir(name)?;
+                }
+            }
+            "mv" => {
+                let [sources @ .., dest] = args else {
+                    return Err(usage("mv <source>... <destination>"));
+                };
+                if sources.is_empty() {
+                    return Err(usage("mv <source>... <destination>"));
+                }
+                check_target_dir(fs, sources, dest)?;
+                for src in sources {
+                    fs.mv(src, dest)?;
+                }
+            }
+            "cp" => {
+                const SYNOPSIS: &str = "cp [-rp] <source>... <destination>";
+                let (flags, names) = split_flags(args);
+                if flags.iter().any(|flag| !"rRp".contains(*flag)) {
+                    return Err(usage(SYNOPSIS));
+                }
+                let [sources @ .., dest] = names.as_slice() else {
+                    return Err(usage(SYNOPSIS));
+                };
+                if sources.is_empty() {
+                    return Err(usage(SYNOPSIS));
+                }
+                let recursive = flags.iter().any(|&flag| flag == 'r' || flag == 'R');
+                let preserve = flags.contains(&'p');
+                check_target_dir(fs, sources, dest)?;
+                for src in sources {
+                    fs.cp(src, dest, recursive, preserve, container_path)?;
+                }
+            }
+            "ln" => match args {
+                [flag, target, link_name] if flag == "-s" => fs.symlink(target, link_name)?,
+                [target, link_name] => fs.link(target, link_name)?,
+                _ => return Err(usage("ln [-s] <target> <link_name>")),
+            },
+            "readlink" => {
+                if let Some(name) = args.first() {
+                    let target = fs.readlink(name)?;
+                    self.stdio.write(format!("{}\n", target).as_bytes());
+                } else {
+                    return Err(usage("readlink <link>"));
+                }
+            }
+            "chmod" => {
+                let (recursive, args) = split_recursive(args);
+                let [mode, names @ ..] = args else {
+                    return Err(usage("chmod [-R] <mode> <file>..."));
+                };
+                if names.is_empty() {
+                    return Err(usage("chmod [-R] <mode> <file>..."));
+                }
+                let change = ModeChange::parse(mode)?;
+                for name in names {
+                    fs.chmod(name, &change, recursive)?;
+                }
+            }
+            "chown" => {
+                let (recursive, args) = split_recursive(args);
+                let [owner, names @ ..] = args else {
+                    return Err(usage("chown [-R] <user>[:<group>] <file>..."));
+                };
+                if names.is_empty() {
+                    return Err(usage("chown [-R] <user>[:<group>] <file>..."));
+                }
+                let (user, group) = match owner.split_once(':') {
+                    Some((user, group)) => (user, Some(group).filter(|group| !group.is_empty())),
+                    None => (owner.as_str(), None),
+                };
+                let user = Some(user).filter(|user| !user.is_empty());
+                if let Some(user) = user {
+                    self.find_user(user)?;
+                }
+                if let Some(group) = group {
+                    self.find_group(group)?;
+                }
+                for name in names {
+                    fs.chown(name, user, group, recursive)?;
+                }
+            }
+            "chgrp" => {
+                let (recursive, args) = split_recursive(args);
+                let [group, names @ ..] = args else {
+                    return Err(usage("chgrp [-R] <group> <file>..."));
+                };
+                if names.is_empty() {
+                    return Err(usage("chgrp [-R] <group> <file>..."));
+                }
+                self.find_group(group)?;
+                for name in names {
+                    fs.chown(name, None, Some(group), recursive)?;
+                }
+            }
+            "groupadd" => {
+                self.require_root()?;
+                let [name] = args else {
+                    return Err(usage("groupadd <group>"));
+                };
+                if self.groups.get_group(name).is_some() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::AlreadyExists,
+                        format!("Group '{}' already exists", name),
+                    ));
+                }
+                self.groups.add_group(Group::new(name));
+                self.groups.save(GROUPS_PATH)?;
+            }
+            "groupdel" => {
+                self.require_root()?;
+                let [name] = args else {
+                    return Err(usage("groupdel <group>"));
+                };
+                self.find_group(name)?;
+                if let Some(user) = self
+                    .users
+                    .get_users()
+                    .iter()
+                    .find(|user| user.get_group_name() == *name)
+                {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!(
+                            "Cannot remove the primary group of user '{}'",
+                            user.get_user_name()
+                        ),
+                    ));
+                }
+                self.groups.remove_group(name);
+                self.groups.save(GROUPS_PATH)?;
+                fs.set_groups(self.groups.groups_of(&self.current_user));
+            }
+            "gpasswd" => {
+                self.require_root()?;
+                let [flag, user, group] = args else {
+                    return Err(usage("gpasswd -a|-d <user> <group>"));
+                };
+                self.find_user(user)?;
+                self.find_group(group)?;
+                match flag.as_str() {
+                    "-a" => {
+                        self.groups.add_member(group, user);
                     }
-
-                    if args.len() > 0 {
-                        if let Err(e) = self.stdio.read_file(args[0], container_path, &fs) {
-                            self.stdio.error(format!("Error: {}", e).as_bytes());
-                            self.stdio.print_error();
-                            continue;
-                        };
-                        self.stdio.print();
-                    } else {
-                        self.stdio.write("Usage: cat <filename>".as_bytes());
-                        self.stdio.print();
+                    "-d" => {
+                        if !self.groups.remove_member(group, user) {
+                            return Err(std::io::Error::new(
+                                std::io::ErrorKind::NotFound,
+                                format!("User '{}' is not a member of '{}'", user, group),
+                            ));
+                        }
                     }
+                    _ => return Err(usage("gpasswd -a|-d <user> <group>")),
                 }
-                "mkdir" => {
-                    if args.len() > 0 {
-                        if let Err(e) = fs.mkdir(args[0]) {
-                            self.stdio.error(format!("Error: {}", e).as_bytes());
-                            self.stdio.print_error();
-                            continue;
-                        };
-                    } else {
-                        self.stdio.write("Usage: mkdir <directory>".as_bytes());
-                        self.stdio.print();
-                    }
+                self.groups.save(GROUPS_PATH)?;
+                fs.set_groups(self.groups.groups_of(&self.current_user));
+            }
+            "passwd" => {
+                let name = match args {
+                    [] => self.current_user.get_user_name(),
+                    [name] => name.clone(),
+                    _ => return Err(usage("passwd [user]")),
+                };
+                let is_root = self.current_user.get_user_name() == "root";
+                if name != self.current_user.get_user_name() {
+                    self.require_root()?;
                 }
-                "rm" => {
-                    if args.len() > 0 {
-                        if let Err(e) = fs.rm(args[0]) {
-                            self.stdio.error(format!("Error: {}", e).as_bytes());
-                            self.stdio.print_error();
-                            continue;
-                        };
-                    } else {
-                        self.stdio.write("Usage: rm <filename>".as_bytes());
-                        self.stdio.print();
-                    }
+
+                let user = self.find_user(&name)?;
+                if !is_root && !user.check_password(&read_password("Current password: ")?) {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::PermissionDenied,
+                        "Authentication failure",
+                    ));
                 }
-                "mv" => {
-                    if args.len() > 1 {
-                        if let Err(e) = fs.mv(args[0], args[1], container_path) {
-                            self.stdio.error(format!("Error: {}", e).as_bytes());
-                            self.stdio.print_error();
-                            continue;
-                        };
+                let password = read_new_password()?;
+
+                if let Some(user) = self.users.get_user_mut(&name) {
+                    user.set_password(&password)?;
+                    if name == self.current_user.get_user_name() {
+                        self.current_user = user.clone();
                     }
                 }
-                "cp" => {
-                    if args.len() > 1 {
-                        if let Err(e) = fs.cp(args[0], args[1], container_path) {
-                            self.stdio.error(format!("Error: {}", e).as_bytes());
-                            self.stdio.print_error();
-                            continue;
-                        };
-                    } else {
-                        self.stdio.write("Usage: cp <source> <destination>".as_bytes());
-                        self.stdio.print();
+                self.users.save(USERS_PATH)?;
+            }
+            "groups" => {
+                let user = match args {
+                    [] => self.current_user.clone(),
+                    [name] => self.find_user(name)?,
+                    _ => return Err(usage("groups [user]")),
+                };
+                let groups = self.groups.groups_of(&user);
+                self.stdio
+                    .write(format!("{}\n", groups.join(" ")).as_bytes());
+            }
+            "id" => {
+                let user = match args {
+                    [] => self.current_user.clone(),
+                    [name] => self.find_user(name)?,
+                    _ => return Err(usage("id [user]")),
+                };
+                let groups = self.groups.groups_of(&user);
+                self.stdio.write(
+                    format!(
+                        "uid={} gid={} groups={}\n",
+                        user.get_user_name(),
+                        user.get_group_name(),
+                        groups.join(",")
+                    )
+                    .as_bytes(),
+                );
+            }
+            "echo" => {
+                self.stdio.write(format!("{}\n", args.join(" ")).as_bytes());
+            }
+            "truncate" => {
+                let [flag, size, name] = args else {
+                    return Err(usage("truncate -s <size> <filename>"));
+                };
+                let size = match size.parse() {
+                    Ok(size) if flag == "-s" => size,
+                    _ => return Err(usage("truncate -s <size> <filename>")),
+                };
+                if !fs.is_file_exists(name) {
+                    fs.touch(name)?;
+                }
+                fs.truncate(name, size, container_path)?;
+            }
+            "head" => {
+                self.head(fs, args, container_path)?;
+            }
+            "tail" => {
+                self.tail(fs, args, container_path)?;
+            }
+            "grep" => {
+                self.grep(fs, args, container_path)?;
+            }
+            "wc" => {
+                self.wc(fs, args, container_path)?;
+            }
+            "su" => {
+                let name = match args {
+                    [] => "root",
+                    [name] => name.as_str(),
+                    _ => return Err(usage("su [user]")),
+                };
+                let user = self.find_user(name)?;
+                if self.current_user.get_user_name() != "root"
+                    && !authenticate(&user, "Password: ")?
+                {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::PermissionDenied,
+                        "Authentication failure",
+                    ));
+                }
+                self.push_user(fs, user);
+            }
+            "sudo" => {
+                let [name, args @ ..] = args else {
+                    return Err(usage("sudo <command> [args]..."));
+                };
+                if self.current_user.get_user_name() != "root" {
+                    let groups = self.groups.groups_of(&self.current_user);
+                    let Some(rule) = self.users.sudo_rule(&self.current_user, &groups, name) else {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::PermissionDenied,
+                            format!(
+                                "{} is not allowed to run '{}' as root",
+                                self.current_user.get_user_name(),
+                                name
+                            ),
+                        ));
+                    };
+                    let prompt = format!(
+                        "[sudo] password for {}: ",
+                        self.current_user.get_user_name()
+                    );
+                    if !rule.is_nopasswd() && !authenticate(&self.current_user, &prompt)? {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::PermissionDenied,
+                            "Authentication failure",
+                        ));
                     }
                 }
-                "echo" => {
-                    if args.contains(&">") {
-                        let data = args.split(|&x| x == ">").collect::<Vec<_>>();
-                        if data.len() != 2 {
-                            self.stdio.write("Usage: echo <data> [> <filename>]".as_bytes());
-                            self.stdio.print();
-                            continue;
-                        } else {
-                            let name = data[1].split(|&x| x == " ").collect::<Vec<_>>();
-                            for i in name {
-                                let i = i.join(" ");
-                                if i != "" {
-                                    if !fs.is_file_exists(i.as_str()) {
-                                        if let Err(e) = fs.touch(i.as_str()) {
-                                            self.stdio.error(format!("Error: {}", e).as_bytes());
-                                            self.stdio.print_error();
-                                            continue;
-                                        };
-                                    }
-                                    if let Err(e) = fs.write_file(i.as_str(), data[0].join(" ").as_bytes(), container_path) {
-                                        self.stdio.error(format!("Error: {}", e).as_bytes());
-                                        self.stdio.print_error();
-                                        continue
-                                    };
-                                }
-                            }
-                        }
+
+                let root = self.users.get_user("root").cloned().unwrap_or_default();
+                let depth = self.previous_users.len();
+                self.push_user(fs, root);
+                let result = self.run_builtin(fs, name, args, container_path);
+                if self.previous_users.len() > depth + 1 {
+                    // `sudo su` switched on from root: keep that user and
+                    // forget root, so `exit` goes back to the caller
+                    self.previous_users.remove(depth + 1);
+                } else if self.previous_users.len() > depth {
+                    // Unless `sudo exit` has already popped
+                    self.pop_user(fs);
+                }
+                result?;
+            }
+            "useradd" => self.useradd(fs, args)?,
+            "userdel" => self.userdel(fs, args)?,
+            "usermod" => self.usermod(fs, args)?,
+            "users" => {
+                self.require_root()?;
+                let mut names: Vec<String> = self
+                    .users
+                    .get_users()
+                    .iter()
+                    .map(|user| user.get_user_name())
+                    .collect();
+                names.sort();
+                self.stdio
+                    .write(format!("{}\n", names.join(" ")).as_bytes());
+            }
+            "who" => {
+                self.require_root()?;
+                let sessions = self.previous_users.iter().chain([&self.current_user]);
+                for (depth, user) in sessions.enumerate() {
+                    let how = if depth == 0 { "login" } else { "su" };
+                    self.stdio
+                        .write(format!("{} ({})\n", user.get_user_name(), how).as_bytes());
+                }
+            }
+            // In a script, `exit` ends only the script
+            "exit" if self.script_depth > 0 => self.script_exited = true,
+            "exit" => {
+                if !self.pop_user(fs) {
+                    self.running = false;
+                }
+            }
+            // A path like `./setup.sh` runs a script
+            _ if command.contains('/') => self.run_script(fs, command, container_path)?,
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("{}: command not found", command),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Tokenizes a command line, expanding `~` to home directories.
+    fn tokenize_line(&self, line: &str) -> std::io::Result<Vec<Token>> {
+        let home = |name: &str| {
+            if name.is_empty() {
+                Some(home_dir(&self.current_user.get_user_name()))
+            } else {
+                self.users
+                    .get_user(name)
+                    .map(|user| home_dir(&user.get_user_name()))
+            }
+        };
+        tokenize(line, home)
+    }
+
+    /// Runs the lines of the file `path` as commands. The file needs the
+    /// execute bit and the user the `can_execute` capability. Everything
+    /// the commands print becomes the output of the script.
+    fn run_script(
+        &mut self,
+        fs: &mut FileSystem,
+        path: &str,
+        container_path: &str,
+    ) -> std::io::Result<()> {
+        fs.check_executable(path)?;
+        if self.script_depth >= MAX_SCRIPT_DEPTH {
+            return Err(std::io::Error::other("Too many nested scripts"));
+        }
+        let script = fs.read_file(path, container_path)?;
+
+        // Scripts don't read from stdin
+        self.stdio.read();
+        let mut output = self.stdio.take_output();
+        self.script_depth += 1;
+        for line in String::from_utf8_lossy(&script).lines() {
+            if !self.running || self.script_exited {
+                break;
+            }
+            // Blank lines, comments and `#!` lines are skipped
+            if line.trim().is_empty() || line.trim_start().starts_with('#') {
+                continue;
+            }
+            match self.tokenize_line(line.trim()) {
+                Ok(tokens) => self.execute_line(fs, &tokens, container_path),
+                Err(e) => self.report_error(&e),
+            }
+            output.extend(self.stdio.take_output());
+        }
+        self.script_depth -= 1;
+        self.script_exited = false;
+
+        self.stdio.write(&output);
+        Ok(())
+    }
+
+    /// Switches to `user`, remembering the current one for `exit`.
+    fn push_user(&mut self, fs: &mut FileSystem, user: User) {
+        let previous = std::mem::replace(&mut self.current_user, user);
+        self.previous_users.push(previous);
+        self.switch_fs_user(fs);
+    }
+
+    /// Returns to the user before the last `su` or `sudo`. Returns false
+    /// if this is the login user.
+    fn pop_user(&mut self, fs: &mut FileSystem) -> bool {
+        let Some(user) = self.previous_users.pop() else {
+            return false;
+        };
+        self.current_user = user;
+        self.switch_fs_user(fs);
+        true
+    }
+
+    fn switch_fs_user(&self, fs: &mut FileSystem) {
+        fs.set_user(self.current_user.clone());
+        fs.set_groups(self.groups.groups_of(&self.current_user));
+    }
+
+    fn require_root(&self) -> std::io::Result<()> {
+        if self.current_user.get_user_name() == "root" {
+            Ok(())
+        } else {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::PermissionDenied,
+                "Permission denied",
+            ))
+        }
+    }
+
+    fn find_user(&self, name: &str) -> std::io::Result<User> {
+        self.users.get_user(name).cloned().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("Invalid user: '{}'", name),
+            )
+        })
+    }
+
+    fn find_group(&self, name: &str) -> std::io::Result<&Group> {
+        self.groups.get_group(name).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("Invalid group: '{}'", name),
+            )
+        })
+    }
+
+    /// Takes the data piped into the current command, failing with `usage`
+    /// when nothing is connected to stdin.
+    fn read_stdin(&mut self, synopsis: &str) -> std::io::Result<Vec<u8>> {
+        self.stdio.read().ok_or_else(|| usage(synopsis))
+    }
+
+    /// Reads every named file, or stdin when no file is given.
+    fn read_inputs(
+        &mut self,
+        fs: &mut FileSystem,
+        files: &[String],
+        container_path: &str,
+        synopsis: &str,
+    ) -> std::io::Result<Vec<(String, Vec<u8>)>> {
+        if files.is_empty() {
+            return Ok(vec![(String::new(), self.read_stdin(synopsis)?)]);
+        }
+        files
+            .iter()
+            .map(|name| Ok((name.clone(), fs.read_file(name, container_path)?)))
+            .collect()
+    }
+
+    fn head(
+        &mut self,
+        fs: &mut FileSystem,
+        args: &[String],
+        container_path: &str,
+    ) -> std::io::Result<()> {
+        let synopsis = "head [-n <lines> | -c <bytes>] [filename]";
+        let (count, name) = parse_count(args).ok_or_else(|| usage(synopsis))?;
+
+        let data = match (count, name) {
+            (Count::Bytes(n), Some(name)) => {
+                let fd = fs.open(name, O_RDONLY, container_path)?;
+                let data = fs.read(fd, n);
+                fs.close(fd)?;
+                data?
+            }
+            (Count::Bytes(n), None) => {
+                let mut data = self.read_stdin(synopsis)?;
+                data.truncate(n as usize);
+                data
+            }
+            (Count::Lines(n), name) => {
+                let data = match name {
+                    Some(name) => fs.read_file(name, container_path)?,
+                    None => self.read_stdin(synopsis)?,
+                };
+                data.split_inclusive(|&b| b == b'\n')
+                    .take(n as usize)
+                    .flatten()
+                    .copied()
+                    .collect()
+            }
+        };
+
+        self.stdio.write(&data);
+        Ok(())
+    }
+
+    fn tail(
+        &mut self,
+        fs: &mut FileSystem,
+        args: &[String],
+        container_path: &str,
+    ) -> std::io::Result<()> {
+        let synopsis = "tail [-n <lines> | -c <bytes>] [filename]";
+        let (count, name) = parse_count(args).ok_or_else(|| usage(synopsis))?;
+
+        let data = match (count, name) {
+            // Only the last `n` bytes of the file are read
+            (Count::Bytes(n), Some(name)) => {
+                let fd = fs.open(name, O_RDONLY, container_path)?;
+                let data = fs
+                    .lseek(fd, SeekFrom::End(0))
+                    .and_then(|size| fs.lseek(fd, SeekFrom::Start(size.saturating_sub(n))))
+                    .and_then(|_| fs.read(fd, n));
+                fs.close(fd)?;
+                data?
+            }
+            (Count::Bytes(n), None) => {
+                let data = self.read_stdin(synopsis)?;
+                data[data.len().saturating_sub(n as usize)..].to_vec()
+            }
+            (Count::Lines(n), name) => {
+                let data = match name {
+                    Some(name) => fs.read_file(name, container_path)?,
+                    None => self.read_stdin(synopsis)?,
+                };
+                let lines: Vec<&[u8]> = data.split_inclusive(|&b| b == b'\n').collect();
+                lines[lines.len().saturating_sub(n as usize)..].concat()
+            }
+        };
+
+        self.stdio.write(&data);
+        Ok(())
+    }
+
+    fn grep(
+        &mut self,
+        fs: &mut FileSystem,
+        args: &[String],
+        container_path: &str,
+    ) -> std::io::Result<()> {
+        let synopsis = "grep [-ivnc] <pattern> [filename]...";
+        let (flags, operands) = split_flags(args);
+        let ignore_case = flags.contains(&'i');
+        let invert = flags.contains(&'v');
+        let line_numbers = flags.contains(&'n');
+        let count_only = flags.contains(&'c');
+
+        let Some((pattern, files)) = operands.split_first() else {
+            return Err(usage(synopsis));
+        };
+        let pattern = if ignore_case {
+            pattern.to_lowercase()
+        } else {
+            pattern.clone()
+        };
+
+        let inputs = self.read_inputs(fs, files, container_path, synopsis)?;
+        let show_names = inputs.len() > 1;
+
+        for (name, data) in inputs {
+            let prefix = if show_names {
+                format!("{}:", name)
+            } else {
+                String::new()
+            };
+            let mut count = 0;
+            for (i, line) in String::from_utf8_lossy(&data).lines().enumerate() {
+                let found = if ignore_case {
+                    line.to_lowercase().contains(&pattern)
+                } else {
+                    line.contains(&pattern)
+                };
+                if found == invert {
+                    continue;
+                }
+                count += 1;
+                if !count_only {
+                    let number = if line_numbers {
+                        format!("{}:", i + 1)
                     } else {
-                        let data = args.join(" ");
-                        self.stdio.write(data.as_bytes());
-                        self.stdio.print();
-                    }
+                        String::new()
+                    };
+                    self.stdio
+                        .write(format!("{}{}{}\n", prefix, number, line).as_bytes());
+                }
+            }
+            if count_only {
+                self.stdio
+                    .write(format!("{}{}\n", prefix, count).as_bytes());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn touch(&mut self, fs: &mut FileSystem, args: &[String]) -> std::io::Result<()> {
+        const SYNOPSIS: &str = "touch [-a] [-m] [-d <date> | -t <[[CC]YY]MMDDhhmm[.ss]>] <file>...";
+        let mut access = false;
+        let mut modification = false;
+        let mut time = None;
+        let mut names = Vec::new();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-a" => access = true,
+                "-m" => modification = true,
+                "-d" => {
+                    let date = args.next().ok_or_else(|| usage(SYNOPSIS))?;
+                    time = Some(Timestamp::parse_date(date)?);
+                }
+                "-t" => {
+                    let stamp = args.next().ok_or_else(|| usage(SYNOPSIS))?;
+                    time = Some(Timestamp::parse_stamp(stamp)?);
+                }
+                _ if arg.len() > 1 && arg.starts_with('-') => return Err(usage(SYNOPSIS)),
+                _ => names.push(arg),
+            }
+        }
+        if names.is_empty() {
+            return Err(usage(SYNOPSIS));
+        }
+        // Without -a or -m both times change
+        if !access && !modification {
+            access = true;
+            modification = true;
+        }
+
+        for name in names {
+            if !fs.is_file_exists(name) {
+                fs.touch(name)?;
+            }
+            fs.set_times(name, time, access, modification)?;
+        }
+        Ok(())
+    }
+
+    fn rm(&mut self, fs: &mut FileSystem, args: &[String]) -> std::io::Result<()> {
+        const SYNOPSIS: &str = "rm [-rfi] [--no-preserve-root] <file>...";
+        let mut preserve_root = true;
+        let mut rest = Vec::new();
+        for arg in args {
+            match arg.as_str() {
+                "--no-preserve-root" => preserve_root = false,
+                "--preserve-root" => preserve_root = true,
+                _ if arg.starts_with("--") => return Err(usage(SYNOPSIS)),
+                _ => rest.push(arg.clone()),
+            }
+        }
+        let (flags, names) = split_flags(&rest);
+
+        let mut recursive = false;
+        let mut force = false;
+        let mut interactive = false;
+        // Of -f and -i the last one wins
+        for flag in flags {
+            match flag {
+                'r' | 'R' => recursive = true,
+                'f' => (force, interactive) = (true, false),
+                'i' => (force, interactive) = (false, true),
+                _ => return Err(usage(SYNOPSIS)),
+            }
+        }
+        if names.is_empty() && !force {
+            return Err(usage(SYNOPSIS));
+        }
+
+        // Like `rm`, a failing operand doesn't stop the others
+        let mut failed = false;
+        for name in &names {
+            let stat = match fs.lstat(name) {
+                Ok(stat) => stat,
+                Err(e) if force && e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    self.write_error(&path_error("remove", name, e));
+                    failed = true;
+                    continue;
+                }
+            };
+            let result = if !recursive || stat.file_type != FileType::Directory {
+                let prompt = format!("rm: remove {} '{}'? ", format::type_name(&stat), name);
+                if interactive && !confirm(&prompt).unwrap_or(false) {
+                    continue;
                 }
-                "exit" => {
-                    fs.save(fs_path)?;
-                    break;
+                fs.rm(name)
+            } else if preserve_root && fs.is_cwd_within(name) {
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "It is the current directory or one above it (use --no-preserve-root)",
+                ))
+            } else if interactive {
+                remove_interactively(fs, name)
+            } else {
+                fs.rm_all(name)
+            };
+            if let Err(e) = result {
+                self.write_error(&path_error("remove", name, e));
+                failed = true;
+            }
+        }
+        if failed {
+            return Err(reported());
+        }
+        Ok(())
+    }
+
+    fn ls(&mut self, fs: &mut FileSystem, args: &[String]) -> std::io::Result<()> {
+        const SYNOPSIS: &str = "ls [-alRhStr1] [--color[=always|never|auto]] [path]...";
+        let mut color = None;
+        let mut rest = Vec::new();
+        for arg in args {
+            match arg.as_str() {
+                "--color" | "--color=always" => color = Some(true),
+                "--color=never" => color = Some(false),
+                "--color=auto" => color = None,
+                _ if arg.starts_with("--") => return Err(usage(SYNOPSIS)),
+                _ => rest.push(arg.clone()),
+            }
+        }
+        let (flags, mut paths) = split_flags(&rest);
+
+        let mut options = LsOptions {
+            color: color.unwrap_or(self.terminal_output),
+            ..LsOptions::default()
+        };
+        for flag in flags {
+            match flag {
+                'l' => options.long = true,
+                'a' => options.all = true,
+                'R' => options.recursive = true,
+                'h' => options.human = true,
+                'S' => options.sort = SortBy::Size,
+                't' => options.sort = SortBy::Time,
+                'r' => options.reverse = true,
+                '1' => options.one_per_line = true,
+                _ => return Err(usage(SYNOPSIS)),
+            }
+        }
+        if paths.is_empty() {
+            paths.push(".".to_string());
+        }
+
+        // Files given by name are listed first, then each directory. Like
+        // `ls`, a bad path doesn't stop the others from being listed.
+        let mut errors = Vec::new();
+        let mut files = Vec::new();
+        let mut dirs = Vec::new();
+        for path in &paths {
+            // A long listing shows a link itself rather than where it points
+            let stat = if options.long {
+                fs.lstat(path)
+            } else {
+                fs.stat(path)
+            };
+            let stat = match stat {
+                Ok(stat) => stat,
+                Err(e) => {
+                    errors.push(path_error("access", path, e));
+                    continue;
+                }
+            };
+            let entry = DirEntry {
+                name: path.clone(),
+                stat,
+            };
+            if entry.stat.file_type == FileType::Directory {
+                dirs.push(entry);
+            } else {
+                files.push(entry);
+            }
+        }
+        format::sort_entries(&mut files, &options);
+        format::sort_entries(&mut dirs, &options);
+
+        let mut listing = String::new();
+        if !files.is_empty() {
+            listing.push_str(&format::listing(&files, &options, false));
+        }
+        let headers = paths.len() > 1 || options.recursive;
+        for dir in &dirs {
+            if !listing.is_empty() {
+                listing.push('\n');
+            }
+            list_dir(fs, &dir.name, &options, headers, &mut listing, &mut errors);
+        }
+        self.stdio.write(listing.as_bytes());
+
+        if errors.is_empty() {
+            return Ok(());
+        }
+        for e in &errors {
+            self.write_error(e);
+        }
+        Err(reported())
+    }
+
+    fn stat(&mut self, fs: &mut FileSystem, args: &[String]) -> std::io::Result<()> {
+        const SYNOPSIS: &str = "stat [-L] [-c <format> | --format=<format>] <file>...";
+        let mut dereference = false;
+        let mut format = None;
+        let mut names = Vec::new();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-L" | "--dereference" => dereference = true,
+                "-c" | "--format" => {
+                    format = Some(args.next().ok_or_else(|| usage(SYNOPSIS))?.as_str());
                 }
-                _ => {
-                    self.stdio.write("Command not found".as_bytes());
-                    self.stdio.print();
+                _ if arg.starts_with("--format=") => format = arg.strip_prefix("--format="),
+                _ if arg.len() > 1 && arg.starts_with('-') => return Err(usage(SYNOPSIS)),
+                _ => names.push(arg),
+            }
+        }
+        if names.is_empty() {
+            return Err(usage(SYNOPSIS));
+        }
+
+        for name in names {
+            let stat = if dereference {
+                fs.stat(name)?
+            } else {
+                fs.lstat(name)?
+            };
+            let text = match format {
+                Some(format) => format!("{}\n", format::stat_format(&stat, name, format)),
+                None => format::stat(&stat, name),
+            };
+            self.stdio.write(text.as_bytes());
+        }
+        Ok(())
+    }
+
+    fn useradd(&mut self, fs: &mut FileSystem, args: &[String]) -> std::io::Result<()> {
+        const SYNOPSIS: &str = "useradd [-g <group>] [-G <group>,...] [-C <rwx>] <user>";
+        self.require_root()?;
+        let options = UserOptions::parse(args, SYNOPSIS)?;
+        if options.append {
+            return Err(usage(SYNOPSIS));
+        }
+        let name = options.name.as_str();
+
+        if name.starts_with('-') || name.contains(['/', ':']) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Invalid user name: '{}'", name),
+            ));
+        }
+        if self.users.get_user(name).is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                format!("User '{}' already exists", name),
+            ));
+        }
+        for group in options.groups.iter().flatten() {
+            self.find_group(group)?;
+        }
+
+        let mut user = User::new(name, true, true, false);
+        if let Some(capabilities) = options.capabilities {
+            user.permissions = capabilities;
+        }
+        match &options.group {
+            Some(group) => {
+                self.find_group(group)?;
+                user.set_group(group);
+            }
+            // Without -g the user gets a group of its own
+            None => {
+                if self.groups.get_group(name).is_some() {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::AlreadyExists,
+                        format!("Group '{}' already exists, use -g to join it", name),
+                    ));
                 }
+                self.groups.add_group(Group::new(name));
+            }
+        }
+        for group in options.groups.iter().flatten() {
+            self.groups.add_member(group, name);
+        }
+
+        self.users.add_user(user.clone());
+        self.users.save(USERS_PATH)?;
+        self.groups.save(GROUPS_PATH)?;
+        self.create_home(fs, &user)
+    }
+
+    fn userdel(&mut self, fs: &mut FileSystem, args: &[String]) -> std::io::Result<()> {
+        self.require_root()?;
+        let (flags, names) = split_flags(args);
+        let [name] = names.as_slice() else {
+            return Err(usage("userdel [-r] <user>"));
+        };
+        if flags.iter().any(|&flag| flag != 'r') {
+            return Err(usage("userdel [-r] <user>"));
+        }
+
+        let user = self.find_user(name)?;
+        if self
+            .previous_users
+            .iter()
+            .chain([&self.current_user])
+            .any(|u| u.get_user_name() == *name)
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("User '{}' is currently logged in", name),
+            ));
+        }
+
+        let home = home_dir(name);
+        if flags.contains(&'r') && fs.is_file_exists(&home) {
+            fs.rm_all(&home)?;
+        }
+
+        self.users.remove_user(name);
+        self.groups.remove_from_all(name);
+        // The user's own group goes with it unless someone else uses it
+        let group = user.get_group_name();
+        if group == *name
+            && !self
+                .users
+                .get_users()
+                .iter()
+                .any(|u| u.get_group_name() == group)
+        {
+            self.groups.remove_group(&group);
+        }
+        self.users.save(USERS_PATH)?;
+        self.groups.save(GROUPS_PATH)?;
+
+        let orphans = fs.find_owned_by(name);
+        if !orphans.is_empty() {
+            self.stdio
+                .error(format!("Warning: files still owned by '{}':\n", name).as_bytes());
+            for path in orphans {
+                self.stdio.error(format!("  {}\n", path).as_bytes());
             }
+        }
+        Ok(())
+    }
 
-            // if let Some(input_file) = input_redirect {
-            //     self.stdio.read_file(&input_file, container_path, &fs)?;
-            // }
+    fn usermod(&mut self, fs: &mut FileSystem, args: &[String]) -> std::io::Result<()> {
+        const SYNOPSIS: &str = "usermod [-g <group>] [-G <group>,... [-a]] [-C <rwx>] <user>";
+        self.require_root()?;
+        let options = UserOptions::parse(args, SYNOPSIS)?;
+        if (options.group.is_none() && options.groups.is_none() && options.capabilities.is_none())
+            || (options.append && options.groups.is_none())
+        {
+            return Err(usage(SYNOPSIS));
+        }
+        let name = options.name.as_str();
 
-            // if let Some(output_file) = output_redirect {
-            //     self.stdio.write_file(&output_file, container_path, &mut fs)?;
-            // }
+        self.find_user(name)?;
+        if let Some(group) = &options.group {
+            self.find_group(group)?;
+        }
+        for group in options.groups.iter().flatten() {
+            self.find_group(group)?;
         }
 
+        if let Some(user) = self.users.get_user_mut(name) {
+            if let Some(group) = &options.group {
+                user.set_group(group);
+            }
+            if let Some(capabilities) = options.capabilities {
+                user.permissions = capabilities;
+            }
+        }
+        if let Some(groups) = &options.groups {
+            if !options.append {
+                self.groups.remove_from_all(name);
+            }
+            for group in groups {
+                self.groups.add_member(group, name);
+            }
+        }
+        self.users.save(USERS_PATH)?;
+        self.groups.save(GROUPS_PATH)?;
+
+        if name == self.current_user.get_user_name() {
+            self.current_user = self.find_user(name)?;
+            self.switch_fs_user(fs);
+        }
         Ok(())
     }
 
-    // fn report_error(&mut self, error: &std::io::Error) {
-    //     // Use stdio or another method to output error to the shell
-    //     self.stdio.input(format!("Error: {}\n", error).as_bytes());
-    //     self.stdio.print();
-    // }
+    /// Creates the home directory of `user`, owned by it, unless it is
+    /// already there.
+    fn create_home(&self, fs: &mut FileSystem, user: &User) -> std::io::Result<()> {
+        let home = home_dir(&user.get_user_name());
+        if fs.is_file_exists(&home) {
+            return Ok(());
+        }
+        fs.mkdir_all(&home)?;
+        fs.chown(
+            &home,
+            Some(&user.get_user_name()),
+            Some(&user.get_group_name()),
+            false,
+        )
+    }
+
+    fn wc(
+        &mut self,
+        fs: &mut FileSystem,
+        args: &[String],
+        container_path: &str,
+    ) -> std::io::Result<()> {
+        let (flags, files) = split_flags(args);
+        // With no flags wc prints all three counts
+        let all = flags.is_empty();
+        let lines = all || flags.contains(&'l');
+        let words = all || flags.contains(&'w');
+        let bytes = all || flags.contains(&'c');
+
+        let inputs = self.read_inputs(fs, &files, container_path, "wc [-lwc] [filename]...")?;
+        let mut total = [0; 3];
+
+        let show_total = inputs.len() > 1;
+        for (name, data) in inputs {
+            let counts = [
+                data.iter().filter(|&&b| b == b'\n').count(),
+                String::from_utf8_lossy(&data).split_whitespace().count(),
+                data.len(),
+            ];
+            for (sum, count) in total.iter_mut().zip(counts) {
+                *sum += count;
+            }
+            self.write_counts(counts, [lines, words, bytes], &name);
+        }
+        if show_total {
+            self.write_counts(total, [lines, words, bytes], "total");
+        }
+
+        Ok(())
+    }
+
+    fn write_counts(&mut self, counts: [usize; 3], selected: [bool; 3], name: &str) {
+        let mut line: Vec<String> = counts
+            .iter()
+            .zip(selected)
+            .filter(|(_, selected)| *selected)
+            .map(|(count, _)| count.to_string())
+            .collect();
+        if !name.is_empty() {
+            line.push(name.to_string());
+        }
+        self.stdio.write(format!("{}\n", line.join(" ")).as_bytes());
+    }
+
+    fn write_error(&mut self, error: &std::io::Error) {
+        if error.get_ref().is_some_and(|inner| inner.is::<Reported>()) {
+            return;
+        }
+        if error.get_ref().is_some_and(|inner| inner.is::<Message>()) {
+            self.stdio.error(format!("{}\n", error).as_bytes());
+        } else {
+            self.stdio.error(format!("Error: {}\n", error).as_bytes());
+        }
+    }
+
+    fn report_error(&mut self, error: &std::io::Error) {
+        self.write_error(error);
+        self.stdio.print_error();
+    }
+}
+
+/// Where a command's stdout or stderr goes.
+#[derive(Debug, Clone, PartialEq)]
+enum Target {
+    /// The pipe or terminal inherited from the shell
+    Inherit,
+    /// The inherited stdout, for `2>&1`
+    Stdout,
+    File {
+        name: String,
+        append: bool,
+    },
+}
+
+/// A simple command with its redirections split off.
+#[derive(Debug)]
+struct Command {
+    args: Vec<String>,
+    input: Option<String>,
+    output: Target,
+    error: Target,
+}
+
+/// Collects the words of a command and applies its redirections left to
+/// right, so `> f 2>&1` and `2>&1 > f` differ the way they do in sh.
+fn parse_command(tokens: &[Token]) -> std::io::Result<Command> {
+    let mut command = Command {
+        args: Vec::new(),
+        input: None,
+        output: Target::Inherit,
+        error: Target::Inherit,
+    };
+
+    let mut iter = tokens.iter();
+    while let Some(token) = iter.next() {
+        if let Token::Word(word) = token {
+            command.args.push(word.clone());
+            continue;
+        }
+        if *token == Token::ErrToOut {
+            command.error = match &command.output {
+                Target::Inherit => Target::Stdout,
+                target => target.clone(),
+            };
+            continue;
+        }
+
+        let name = match iter.next() {
+            Some(Token::Word(name)) => name.clone(),
+            Some(next) => {
+                return Err(syntax_error(&format!(
+                    "unexpected token '{}'",
+                    next.as_str()
+                )))
+            }
+            None => {
+                return Err(syntax_error(&format!(
+                    "missing file name after '{}'",
+                    token.as_str()
+                )))
+            }
+        };
+        match token {
+            Token::RedirectIn => command.input = Some(name),
+            Token::RedirectOut => {
+                command.output = Target::File {
+                    name,
+                    append: false,
+                }
+            }
+            Token::RedirectAppend => command.output = Target::File { name, append: true },
+            Token::RedirectErr => {
+                command.error = Target::File {
+                    name,
+                    append: false,
+                }
+            }
+            Token::RedirectErrAppend => command.error = Target::File { name, append: true },
+            token => {
+                return Err(syntax_error(&format!(
+                    "unexpected token '{}'",
+                    token.as_str()
+                )))
+            }
+        }
+    }
+
+    if command.args.is_empty()
+        && command.input.is_none()
+        && command.output == Target::Inherit
+        && command.error == Target::Inherit
+    {
+        return Err(syntax_error("missing command"));
+    }
+
+    Ok(command)
+}
+
+/// Splits a token stream at `;`, `&&` and `||`, pairing every command with
+/// the operator that precedes it (`;` for the first one).
+fn split_list(tokens: &[Token]) -> std::io::Result<Vec<(Token, &[Token])>> {
+    let mut commands = Vec::new();
+    let mut connector = Token::Semicolon;
+    let mut rest = tokens;
+
+    while !rest.is_empty() {
+        let end = rest
+            .iter()
+            .position(|token| matches!(token, Token::Semicolon | Token::And | Token::Or))
+            .unwrap_or(rest.len());
+        let (command, tail) = rest.split_at(end);
+
+        if command.is_empty() {
+            return Err(syntax_error(&format!(
+                "unexpected token '{}'",
+                rest[0].as_str()
+            )));
+        }
+        commands.push((connector.clone(), command));
+
+        match tail.split_first() {
+            Some((Token::Semicolon, tail)) => {
+                connector = Token::Semicolon;
+                rest = tail;
+            }
+            Some((operator, tail)) => {
+                if tail.is_empty() {
+                    return Err(syntax_error(&format!(
+                        "missing command after '{}'",
+                        operator.as_str()
+                    )));
+                }
+                connector = operator.clone();
+                rest = tail;
+            }
+            None => rest = tail,
+        }
+    }
+
+    Ok(commands)
+}
+
+fn append_to_file(
+    fs: &mut FileSystem,
+    name: &str,
+    data: &[u8],
+    container_path: &str,
+) -> std::io::Result<()> {
+    let fd = fs.open(name, O_WRONLY | O_APPEND, container_path)?;
+    let result = fs.write(fd, data);
+    fs.close(fd)?;
+    result.map(|_| ())
+}
+
+/// How much of the input `head` and `tail` keep.
+enum Count {
+    Lines(u64),
+    Bytes(u64),
+}
+
+/// Parses `[-n <lines> | -c <bytes>] [filename]`, defaulting to 10 lines.
+fn parse_count(args: &[String]) -> Option<(Count, Option<&String>)> {
+    match args {
+        [flag, count, rest @ ..] if flag == "-n" || flag == "-c" => {
+            let count = count.parse().ok()?;
+            let count = if flag == "-n" {
+                Count::Lines(count)
+            } else {
+                Count::Bytes(count)
+            };
+            match rest {
+                [] => Some((count, None)),
+                [name] => Some((count, Some(name))),
+                _ => None,
+            }
+        }
+        [] => Some((Count::Lines(10), None)),
+        [name] if !name.starts_with('-') => Some((Count::Lines(10), Some(name))),
+        _ => None,
+    }
+}
+
+/// Appends the listing of the directory `path`, and with `-R` of its
+/// subdirectories.
+fn list_dir(
+    fs: &mut FileSystem,
+    path: &str,
+    options: &LsOptions,
+    header: bool,
+    listing: &mut String,
+    errors: &mut Vec<std::io::Error>,
+) {
+    let mut entries = match fs.read_dir(path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            errors.push(path_error("open directory", path, e));
+            return;
+        }
+    };
+    if !options.all {
+        entries.retain(|entry| !entry.name.starts_with('.'));
+    }
+    format::sort_entries(&mut entries, options);
+
+    if header {
+        listing.push_str(&format!("{}:\n", path));
+    }
+    listing.push_str(&format::listing(&entries, options, true));
+
+    if options.recursive {
+        for entry in &entries {
+            if entry.stat.file_type != FileType::Directory
+                || entry.name == "."
+                || entry.name == ".."
+            {
+                continue;
+            }
+            let child = if path.ends_with('/') {
+                format!("{}{}", path, entry.name)
+            } else {
+                format!("{}/{}", path, entry.name)
+            };
+            listing.push('\n');
+            list_dir(fs, &child, options, true, listing, errors);
+        }
+    }
+}
+
+/// Says which path a command failed on, e.g. `Cannot access 'x': ...`.
+fn path_error(action: &str, path: &str, error: std::io::Error) -> std::io::Error {
+    std::io::Error::new(
+        error.kind(),
+        format!("Cannot {} '{}': {}", action, path, error),
+    )
+}
+
+/// `rm -ri`: asks before descending into each directory and before removing
+/// anything. A directory is only removed once it is empty.
+fn remove_interactively(fs: &mut FileSystem, name: &str) -> std::io::Result<()> {
+    let stat = fs.lstat(name)?;
+    if stat.file_type != FileType::Directory {
+        if confirm(&format!(
+            "rm: remove {} '{}'? ",
+            format::type_name(&stat),
+            name
+        ))? {
+            fs.rm(name)?;
+        }
+        return Ok(());
+    }
+
+    if !confirm(&format!("rm: descend into directory '{}'? ", name))? {
+        return Ok(());
+    }
+    for entry in fs.read_dir(name)? {
+        if entry.name == "." || entry.name == ".." {
+            continue;
+        }
+        let child = format!("{}/{}", name.trim_end_matches('/'), entry.name);
+        remove_interactively(fs, &child)?;
+    }
+    if confirm(&format!("rm: remove directory '{}'? ", name))? {
+        fs.rmdir(name)?;
+    }
+    Ok(())
+}
+
+/// `cp` and `mv` with several sources need a directory to put them in.
+fn check_target_dir(fs: &FileSystem, sources: &[String], dest: &str) -> std::io::Result<()> {
+    let is_dir = fs
+        .stat(dest)
+        .is_ok_and(|stat| stat.file_type == FileType::Directory);
+    if sources.len() > 1 && !is_dir {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotADirectory,
+            format!("Target '{}' is not a directory", dest),
+        ));
+    }
+    Ok(())
+}
+
+/// Separates single-letter flags (`-l`, `-in`) from operands.
+fn split_flags(args: &[String]) -> (Vec<char>, Vec<String>) {
+    let mut flags = Vec::new();
+    let mut operands = Vec::new();
+    for arg in args {
+        if arg.len() > 1 && arg.starts_with('-') {
+            flags.extend(arg.chars().skip(1));
+        } else {
+            operands.push(arg.clone());
+        }
+    }
+    (flags, operands)
+}
+
+/// Asks for the password of `user`. With `password_stdin` the first line of
+/// stdin is taken as the password instead, so scripts can log in. Accounts
+/// without a password are locked, as for `su`.
+fn login(user: &User, password_stdin: bool) -> std::io::Result<()> {
+    if password_stdin {
+        if user.has_password() && user.check_password(&read_stdin_line()?) {
+            return Ok(());
+        }
+    } else if authenticate(user, "Password: ")? {
+        return Ok(());
+    }
+    Err(login_incorrect())
+}
+
+/// Asks for the password of `user`, giving three tries. An account without a
+/// password is locked: only root may switch to it, and it can't use `sudo`.
+fn authenticate(user: &User, prompt: &str) -> std::io::Result<bool> {
+    if !user.has_password() {
+        return Ok(false);
+    }
+    for attempt in 1..=PASSWORD_ATTEMPTS {
+        if user.check_password(&read_password(prompt)?) {
+            return Ok(true);
+        }
+        if attempt < PASSWORD_ATTEMPTS {
+            eprintln!("Sorry, try again.");
+        }
+    }
+    Ok(false)
+}
+
+fn login_incorrect() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::PermissionDenied, "Login incorrect")
+}
+
+/// The password for a new account: one line of stdin with `password_stdin`,
+/// otherwise asked for twice.
+fn choose_password(password_stdin: bool) -> std::io::Result<String> {
+    if !password_stdin {
+        return read_new_password();
+    }
+    let password = read_stdin_line()?;
+    if password.is_empty() {
+        return Err(empty_password());
+    }
+    Ok(password)
+}
+
+/// Asks for a new password twice, refusing an empty one and a mismatch.
+fn read_new_password() -> std::io::Result<String> {
+    let password = read_password("New password: ")?;
+    if password.is_empty() {
+        return Err(empty_password());
+    }
+    if read_password("Retype new password: ")? != password {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "Passwords do not match",
+        ));
+    }
+    Ok(password)
+}
+
+fn empty_password() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        "Password must not be empty",
+    )
+}
+
+/// Reads one line of stdin, without the line break.
+fn read_stdin_line() -> std::io::Result<String> {
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Asks a yes/no question on the terminal. Anything but an answer starting
+/// with `y` is a no.
+fn confirm(prompt: &str) -> std::io::Result<bool> {
+    print!("{}", prompt);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim_start().starts_with(['y', 'Y']))
+}
+
+/// Reads a line without echoing it when stdin is a terminal.
+fn read_password(prompt: &str) -> std::io::Result<String> {
+    if io::stdin().is_terminal() {
+        return rpassword::prompt_password(prompt);
+    }
+
+    print!("{}", prompt);
+    io::stdout().flush()?;
+    let mut password = String::new();
+    if io::stdin().read_line(&mut password)? == 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::UnexpectedEof,
+            "No password given",
+        ));
+    }
+    println!();
+    Ok(password.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Options shared by useradd and usermod.
+struct UserOptions {
+    /// `-g`, the primary group
+    group: Option<String>,
+    /// `-G`, supplementary groups
+    groups: Option<Vec<String>>,
+    /// `-a`, add to the supplementary groups instead of replacing them
+    append: bool,
+    /// `-C`, the capability flags as any of `rwx`, or `-` for none
+    capabilities: Option<Permissions>,
+    name: String,
+}
+
+impl UserOptions {
+    fn parse(args: &[String], synopsis: &str) -> std::io::Result<Self> {
+        let mut group = None;
+        let mut groups = None;
+        let mut append = false;
+        let mut capabilities = None;
+        let mut name = None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-g" => group = Some(args.next().ok_or_else(|| usage(synopsis))?.clone()),
+                // `-aG` is the usual spelling of `-a -G`
+                "-G" | "-aG" => {
+                    append |= arg == "-aG";
+                    let list = args.next().ok_or_else(|| usage(synopsis))?;
+                    groups = Some(
+                        list.split(',')
+                            .filter(|group| !group.is_empty())
+                            .map(str::to_string)
+                            .collect(),
+                    );
+                }
+                "-a" => append = true,
+                "-C" => {
+                    let letters = args.next().ok_or_else(|| usage(synopsis))?;
+                    if letters != "-" && !letters.chars().all(|c| "rwx".contains(c)) {
+                        return Err(usage(synopsis));
+                    }
+                    capabilities = Some(Permissions {
+                        can_read: letters.contains('r'),
+                        can_write: letters.contains('w'),
+                        can_execute: letters.contains('x'),
+                    });
+                }
+                _ if name.is_none() && !arg.starts_with('-') => name = Some(arg.clone()),
+                _ => return Err(usage(synopsis)),
+            }
+        }
+
+        Ok(Self {
+            group,
+            groups,
+            append,
+            capabilities,
+            name: name.ok_or_else(|| usage(synopsis))?,
+        })
+    }
+}
+
+/// Home directory of the user `name`.
+fn home_dir(name: &str) -> String {
+    if name == "root" {
+        "/root".to_string()
+    } else {
+        format!("/home/{}", name)
+    }
+}
+
+/// Strips a leading `-R` from the arguments of chmod, chown and chgrp, where
+/// modes like `-w` can't go through `split_flags`.
+fn split_recursive(args: &[String]) -> (bool, &[String]) {
+    match args {
+        [flag, rest @ ..] if flag == "-R" => (true, rest),
+        _ => (false, args),
+    }
+}
+
+/// An error whose details the command has already written, leaving only the
+/// failure to report.
+#[derive(Debug)]
+struct Reported;
+
+impl fmt::Display for Reported {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Command failed")
+    }
+}
+
+impl std::error::Error for Reported {}
+
+fn reported() -> std::io::Error {
+    std::io::Error::other(Reported)
+}
+
+fn usage(synopsis: &str) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        Message(format!("Usage: {}", synopsis)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shell() -> Shell {
+        let mut users = Users::new();
+        users.add_user(User::default());
+        users.add_user(User::new("bob", true, true, false));
+        Shell {
+            config: Config::default(),
+            current_user: User::default(),
+            previous_users: Vec::new(),
+            users,
+            groups: Groups::new(),
+            stdio: Stdio::new(),
+            running: true,
+            script_depth: 0,
+            script_exited: false,
+            terminal_output: false,
+        }
+    }
+
+    fn run(shell: &mut Shell, fs: &mut FileSystem, line: &str) {
+        let tokens = shell.tokenize_line(line).unwrap();
+        shell.execute_line(fs, &tokens, "unused.bin");
+    }
+
+    #[test]
+    fn rm_goes_on_past_a_failing_operand() {
+        let mut shell = shell();
+        let mut fs = FileSystem::new(User::default());
+        run(&mut shell, &mut fs, "touch a b; mkdir d");
+        run(&mut shell, &mut fs, "rm a missing d b || touch failed");
+        assert!(!fs.is_file_exists("a"));
+        assert!(!fs.is_file_exists("b"));
+        assert!(fs.is_file_exists("d"));
+        assert!(fs.is_file_exists("failed"));
+    }
+
+    #[test]
+    fn sudo_su_keeps_the_new_user() {
+        let mut shell = shell();
+        let mut fs = FileSystem::new(User::default());
+        run(&mut shell, &mut fs, "sudo su bob");
+        assert_eq!(shell.current_user.get_user_name(), "bob");
+        assert_eq!(shell.previous_users.len(), 1);
+        run(&mut shell, &mut fs, "exit");
+        assert_eq!(shell.current_user.get_user_name(), "root");
+        assert!(shell.previous_users.is_empty());
+        assert!(shell.running);
+    }
+
+    #[test]
+    fn sudo_returns_to_the_caller() {
+        let mut shell = shell();
+        let mut fs = FileSystem::new(User::default());
+        run(&mut shell, &mut fs, "sudo mkdir /x");
+        assert_eq!(shell.current_user.get_user_name(), "root");
+        assert!(shell.previous_users.is_empty());
+        assert!(fs.stat("/x").is_ok());
+    }
 }
@@ -0,0 +1,111 @@
+use std::{
+    fs::{File, OpenOptions},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::User;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Groups {
+    groups: Vec<Group>,
+}
+
+/// A group and the users having it as a supplementary group. Users whose
+/// primary group it is aren't listed in `members`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Group {
+    name: String,
+    members: Vec<String>,
+}
+
+impl Group {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            members: Vec::new(),
+        }
+    }
+
+    pub fn get_group_name(&self) -> String {
+        self.name.to_string()
+    }
+}
+
+impl Groups {
+    pub fn new() -> Self {
+        Self { groups: Vec::new() }
+    }
+
+    pub fn add_group(&mut self, group: Group) {
+        self.groups.push(group);
+    }
+
+    pub fn remove_group(&mut self, name: &str) -> Option<Group> {
+        let index = self.groups.iter().position(|group| group.name == name)?;
+        Some(self.groups.remove(index))
+    }
+
+    pub fn get_group(&self, name: &str) -> Option<&Group> {
+        self.groups.iter().find(|group| group.name == name)
+    }
+
+    /// Adds `username` to the members of `group`. Returns false if there is
+    /// no such group.
+    pub fn add_member(&mut self, group: &str, username: &str) -> bool {
+        let Some(group) = self.groups.iter_mut().find(|g| g.name == group) else {
+            return false;
+        };
+        if !group.members.iter().any(|member| member == username) {
+            group.members.push(username.to_string());
+        }
+        true
+    }
+
+    /// Removes `username` from the members of `group`. Returns false if it
+    /// wasn't a member.
+    pub fn remove_member(&mut self, group: &str, username: &str) -> bool {
+        let Some(group) = self.groups.iter_mut().find(|g| g.name == group) else {
+            return false;
+        };
+        let len = group.members.len();
+        group.members.retain(|member| member != username);
+        group.members.len() != len
+    }
+
+    /// Takes `username` out of every group it is a member of.
+    pub fn remove_from_all(&mut self, username: &str) {
+        for group in &mut self.groups {
+            group.members.retain(|member| member != username);
+        }
+    }
+
+    /// The primary group of `user` followed by its supplementary groups.
+    pub fn groups_of(&self, user: &User) -> Vec<String> {
+        let primary = user.get_group_name();
+        let mut groups = vec![primary.clone()];
+        for group in &self.groups {
+            if group.name != primary && group.members.contains(&user.get_user_name()) {
+                groups.push(group.get_group_name());
+            }
+        }
+        groups
+    }
+
+    pub fn save<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<()> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        serde_json::to_writer(file, self)?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let file = File::open(path)?;
+        let groups: Groups = serde_json::from_reader(file)?;
+        Ok(groups)
+    }
+}
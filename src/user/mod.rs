@@ -1,3 +1,6 @@
+pub mod group;
+#[allow(clippy::module_inception)]
 pub mod user;
 
-pub use user::*;
\ No newline at end of file
+pub use group::*;
+pub use user::*;
@@ -4,15 +4,66 @@ use std::{
 };
 
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Users {
     users: Vec<User>,
+    /// Who may run what through `sudo`
+    #[serde(default = "default_sudoers")]
+    sudoers: Vec<SudoRule>,
+}
+
+/// A sudoers entry letting `principal`, a user name or `%group`, run
+/// `commands` as root, or every command when `commands` is empty.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SudoRule {
+    principal: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    commands: Vec<String>,
+    /// Skip asking for the user's password
+    #[serde(default)]
+    nopasswd: bool,
+}
+
+impl SudoRule {
+    pub fn new(principal: &str, commands: Vec<String>, nopasswd: bool) -> Self {
+        Self {
+            principal: principal.to_string(),
+            commands,
+            nopasswd,
+        }
+    }
+
+    pub fn is_nopasswd(&self) -> bool {
+        self.nopasswd
+    }
+
+    /// Whether the rule lets `user`, a member of `groups`, run `command`.
+    pub fn permits(&self, user: &User, groups: &[String], command: &str) -> bool {
+        let principal = match self.principal.strip_prefix('%') {
+            Some(group) => groups.iter().any(|g| g == group),
+            None => self.principal == user.username,
+        };
+        principal && (self.commands.is_empty() || self.commands.iter().any(|c| c == command))
+    }
+}
+
+/// Members of the `sudo` group may run anything, as on Debian.
+fn default_sudoers() -> Vec<SudoRule> {
+    vec![SudoRule::new("%sudo", Vec::new(), false)]
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     username: String,
+    /// Primary group, the group named after the user when `None`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    group: Option<String>,
+    /// Salted password hash, see `hash_password`. Users without one log in
+    /// without being asked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    password: Option<String>,
     pub permissions: Permissions,
 }
 
@@ -27,6 +78,8 @@ impl User {
     pub fn new(username: &str, can_read: bool, can_write: bool, can_execute: bool) -> Self {
         Self {
             username: username.to_string(),
+            group: None,
+            password: None,
             permissions: Permissions {
                 can_read,
                 can_write,
@@ -35,8 +88,64 @@ impl User {
         }
     }
     pub fn get_user_name(&self) -> String {
-        return self.username.to_string();
+        self.username.to_string()
+    }
+
+    pub fn get_group_name(&self) -> String {
+        self.group.clone().unwrap_or_else(|| self.get_user_name())
+    }
+
+    pub fn set_group(&mut self, group: &str) {
+        self.group = Some(group.to_string());
+    }
+
+    pub fn has_password(&self) -> bool {
+        self.password.is_some()
     }
+
+    /// Stores a hash of `password` under a new random salt.
+    pub fn set_password(&mut self, password: &str) -> std::io::Result<()> {
+        let mut salt = [0; 16];
+        getrandom::getrandom(&mut salt)?;
+        self.password = Some(hash_password(password, &salt));
+        Ok(())
+    }
+
+    /// Whether `password` is correct. Always true for users without one.
+    pub fn check_password(&self, password: &str) -> bool {
+        let Some(stored) = &self.password else {
+            return true;
+        };
+        let salt = stored
+            .split('$')
+            .nth(1)
+            .and_then(from_hex)
+            .unwrap_or_default();
+        hash_password(password, &salt) == *stored
+    }
+}
+
+/// Hashes `password` into `sha256$<salt>$<digest>`, both in hex, where the
+/// digest is SHA-256 over the salt followed by the password.
+fn hash_password(password: &str, salt: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(password.as_bytes());
+    format!("sha256${}${}", to_hex(salt), to_hex(&hasher.finalize()))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
 }
 
 impl Default for User {
@@ -49,6 +158,7 @@ impl Users {
     pub fn new() -> Self {
         Self {
             users: Vec::new(),
+            sudoers: default_sudoers(),
         }
     }
 
@@ -56,8 +166,31 @@ impl Users {
         self.users.push(user);
     }
 
+    pub fn remove_user(&mut self, username: &str) -> Option<User> {
+        let index = self
+            .users
+            .iter()
+            .position(|user| user.username == username)?;
+        Some(self.users.remove(index))
+    }
+
     pub fn get_users(&self) -> &Vec<User> {
-        return &self.users;
+        &self.users
+    }
+
+    pub fn get_user(&self, username: &str) -> Option<&User> {
+        self.users.iter().find(|user| user.username == username)
+    }
+
+    /// The first sudoers rule allowing `user` to run `command`.
+    pub fn sudo_rule(&self, user: &User, groups: &[String], command: &str) -> Option<&SudoRule> {
+        self.sudoers
+            .iter()
+            .find(|rule| rule.permits(user, groups, command))
+    }
+
+    pub fn get_user_mut(&mut self, username: &str) -> Option<&mut User> {
+        self.users.iter_mut().find(|user| user.username == username)
     }
 
     pub fn save<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<()> {